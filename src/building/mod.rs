@@ -0,0 +1,195 @@
+//! The buildings sold in the store, with the numbers the game uses for them.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::notation::CookieAmount;
+
+/// Every building, in store order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Building {
+    Cursor,
+    Grandma,
+    Farm,
+    Mine,
+    Factory,
+    Bank,
+    Temple,
+    WizardTower,
+    Shipment,
+    AlchemyLab,
+    Portal,
+    TimeMachine,
+    AntimatterCondenser,
+    Prism,
+    Chancemaker,
+    FractalEngine,
+    JavascriptConsole,
+    Idleverse,
+    CortexBaker,
+    You,
+}
+
+/// Each building costs this much more than the one before it.
+const PRICE_INCREASE: f64 = 1.15;
+
+impl Building {
+    pub const ALL: [Building; 20] = [
+        Building::Cursor,
+        Building::Grandma,
+        Building::Farm,
+        Building::Mine,
+        Building::Factory,
+        Building::Bank,
+        Building::Temple,
+        Building::WizardTower,
+        Building::Shipment,
+        Building::AlchemyLab,
+        Building::Portal,
+        Building::TimeMachine,
+        Building::AntimatterCondenser,
+        Building::Prism,
+        Building::Chancemaker,
+        Building::FractalEngine,
+        Building::JavascriptConsole,
+        Building::Idleverse,
+        Building::CortexBaker,
+        Building::You,
+    ];
+
+    /// Position in the store, 0 for the top row.
+    #[inline]
+    pub fn store_order(self) -> usize {
+        self as usize
+    }
+
+    /// The name the store shows.
+    pub fn name(self) -> &'static str {
+        match self {
+            Building::Cursor => "Cursor",
+            Building::Grandma => "Grandma",
+            Building::Farm => "Farm",
+            Building::Mine => "Mine",
+            Building::Factory => "Factory",
+            Building::Bank => "Bank",
+            Building::Temple => "Temple",
+            Building::WizardTower => "Wizard tower",
+            Building::Shipment => "Shipment",
+            Building::AlchemyLab => "Alchemy lab",
+            Building::Portal => "Portal",
+            Building::TimeMachine => "Time machine",
+            Building::AntimatterCondenser => "Antimatter condenser",
+            Building::Prism => "Prism",
+            Building::Chancemaker => "Chancemaker",
+            Building::FractalEngine => "Fractal engine",
+            Building::JavascriptConsole => "Javascript console",
+            Building::Idleverse => "Idleverse",
+            Building::CortexBaker => "Cortex baker",
+            Building::You => "You",
+        }
+    }
+
+    /// Price of the first one.
+    pub fn base_cost(self) -> CookieAmount {
+        CookieAmount(match self {
+            Building::Cursor => 15.0,
+            Building::Grandma => 100.0,
+            Building::Farm => 1_100.0,
+            Building::Mine => 12_000.0,
+            Building::Factory => 130_000.0,
+            Building::Bank => 1.4e6,
+            Building::Temple => 2e7,
+            Building::WizardTower => 3.3e8,
+            Building::Shipment => 5.1e9,
+            Building::AlchemyLab => 7.5e10,
+            Building::Portal => 1e12,
+            Building::TimeMachine => 1.4e13,
+            Building::AntimatterCondenser => 1.7e14,
+            Building::Prism => 2.1e15,
+            Building::Chancemaker => 2.6e16,
+            Building::FractalEngine => 3.1e17,
+            Building::JavascriptConsole => 7.1e19,
+            Building::Idleverse => 1.2e22,
+            Building::CortexBaker => 1.9e24,
+            Building::You => 5.4e26,
+        })
+    }
+
+    /// Cookies per second one of them makes before any upgrades.
+    pub fn base_cps(self) -> CookieAmount {
+        CookieAmount(match self {
+            Building::Cursor => 0.1,
+            Building::Grandma => 1.0,
+            Building::Farm => 8.0,
+            Building::Mine => 47.0,
+            Building::Factory => 260.0,
+            Building::Bank => 1_400.0,
+            Building::Temple => 7_800.0,
+            Building::WizardTower => 44_000.0,
+            Building::Shipment => 260_000.0,
+            Building::AlchemyLab => 1.6e6,
+            Building::Portal => 1e7,
+            Building::TimeMachine => 6.5e7,
+            Building::AntimatterCondenser => 4.3e8,
+            Building::Prism => 2.9e9,
+            Building::Chancemaker => 2.1e10,
+            Building::FractalEngine => 1.5e11,
+            Building::JavascriptConsole => 1.1e12,
+            Building::Idleverse => 8.3e12,
+            Building::CortexBaker => 6.4e13,
+            Building::You => 5.1e14,
+        })
+    }
+
+    /// Undiscounted price of the next one when `owned` are already bought.
+    pub fn price(self, owned: u32) -> CookieAmount {
+        CookieAmount((self.base_cost().value() * PRICE_INCREASE.powi(owned as i32)).ceil())
+    }
+
+    /// The building that sells for about `price` when `owned` are already bought.
+    /// Upgrades and auras can knock a fair bit off a price, but neighbouring buildings
+    /// are an order of magnitude apart, so anything within half to double still counts.
+    pub fn identify(price: CookieAmount, owned: u32) -> Option<Building> {
+        let distance = |building: &Building| (price.value() / building.price(owned).value()).ln().abs();
+        Building::ALL.iter()
+            .copied()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .filter(|building| distance(building) < 2f64.ln())
+    }
+}
+
+impl fmt::Display for Building {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_rise_by_fifteen_percent_rounded_up() {
+        assert_eq!(Building::Cursor.price(0).value(), 15.0);
+        assert_eq!(Building::Cursor.price(1).value(), 18.0);
+        assert_eq!(Building::Cursor.price(10).value(), 61.0);
+        assert_eq!(Building::Grandma.price(0).value(), 100.0);
+        assert_eq!(Building::Farm.price(2).value(), 1_455.0);
+    }
+
+    #[test]
+    fn identifies_a_row_by_its_price() {
+        for building in Building::ALL {
+            assert_eq!(Building::identify(building.price(5), 5), Some(building));
+        }
+        assert_eq!(Building::identify(CookieAmount(20.0), 0), Some(Building::Cursor));
+        assert_eq!(Building::identify(CookieAmount(1.0), 0), None);
+        assert_eq!(Building::identify(CookieAmount(1e40), 0), None);
+    }
+
+    #[test]
+    fn store_order_follows_all() {
+        for (i, building) in Building::ALL.iter().enumerate() {
+            assert_eq!(building.store_order(), i);
+        }
+    }
+}
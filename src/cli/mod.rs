@@ -0,0 +1,82 @@
+//! Command line arguments.
+//!
+//! Without a subcommand the bot runs, as it always has. `--screen`, `--config`
+//! and `--dry-run` go before or after any subcommand.
+
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use crate::config;
+
+#[derive(Debug, Parser)]
+#[command(name = "cookie_monster", about = "Plays Cookie Clicker by looking at the screen.")]
+pub struct Cli {
+    #[command(flatten)]
+    pub common: Common,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct Common {
+    /// Monitor to play on, numbered as `monitors` lists them.
+    #[arg(long, global = true, default_value_t = 0)]
+    pub screen: usize,
+    /// Config file to load.
+    #[arg(long, global = true, default_value = config::DEFAULT_PATH)]
+    pub config: PathBuf,
+    /// Log what would be clicked and why instead of touching the mouse, and
+    /// don't write files. The big cookie isn't clicked at all.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Play the game.
+    Run {
+        /// Replay a PNG, or a directory of them named by millisecond timestamp,
+        /// instead of capturing the screen.
+        replay: Option<PathBuf>,
+        /// Replay a directory without waiting between frames.
+        #[arg(long, requires = "replay")]
+        fast: bool,
+    },
+    /// Point at everything the bot uses and save it as a layout profile.
+    Calibrate {
+        /// Profile name, saved to profiles/<name>.toml.
+        name: String,
+    },
+    /// List the monitors, with their size and place on the desktop.
+    Monitors,
+    /// Save one frame to a PNG.
+    Capture {
+        /// Defaults to frame-<unix millis>.png.
+        output: Option<PathBuf>,
+    },
+    /// Read a number off a PNG or the screen with a set of digit templates.
+    Ocr {
+        /// Read this PNG instead of the screen.
+        #[arg(long)]
+        png: Option<PathBuf>,
+        /// Only look inside this rectangle.
+        #[arg(long, num_args = 4, value_names = ["X", "Y", "WIDTH", "HEIGHT"])]
+        region: Option<Vec<usize>>,
+        /// Digit templates to read with, the config's bank templates by default.
+        #[arg(long)]
+        templates: Option<PathBuf>,
+        /// Colour of the digits' ink.
+        #[arg(long, num_args = 3, value_names = ["R", "G", "B"])]
+        colour: Option<Vec<u8>>,
+        /// How far off `colour` ink may be, per channel.
+        #[arg(long)]
+        tolerance: Option<u8>,
+    },
+    /// Print the colour the bot sees at a pixel.
+    Inspect {
+        x: i32,
+        y: i32,
+        /// Look at this PNG instead of the screen.
+        #[arg(long)]
+        png: Option<PathBuf>,
+    },
+}
@@ -0,0 +1,164 @@
+//! Picks how fast the auto clicker goes from the buffs that are running.
+//!
+//! A click is worth more the more production and click multipliers are stacked
+//! on it, so the clicker goes flat out while the stack is worth it, idles at a
+//! low rate while nothing is running, and otherwise keeps its usual cadence.
+//! The first time a stack crosses the threshold, Force the Hand of Fate can be
+//! cast on top of it, hoping for another buff.
+
+use std::fmt;
+use std::time::Duration;
+use crate::config::{ClickerConfig, ComboConfig};
+use crate::detect::buffs::ActiveBuff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pace {
+    /// Nothing is running.
+    Idle,
+    /// Something is running, or the buff bar isn't read.
+    Normal,
+    /// The stack is past the threshold.
+    Max,
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pace::Idle => write!(f, "idling"),
+            Pace::Normal => write!(f, "clicking as usual"),
+            Pace::Max => write!(f, "clicking flat out"),
+        }
+    }
+}
+
+/// What the auto clicker should do after `ComboEngine::update`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub pace: Pace,
+    /// Set once per combo, when Force the Hand of Fate should be cast.
+    pub cast: Option<(i32, i32)>,
+}
+
+pub struct ComboEngine {
+    config: ComboConfig,
+    pace: Pace,
+    /// Whether the current combo has had its spell, so it's cast only once.
+    cast: bool,
+}
+
+impl ComboEngine {
+    pub fn new(config: ComboConfig) -> Self {
+        Self { config, pace: Pace::Normal, cast: false }
+    }
+
+    /// Pause between two clicks at the current pace.
+    pub fn click_delay(&self, clicker: &ClickerConfig) -> Duration {
+        match self.pace {
+            Pace::Idle => self.config.idle_delay(),
+            Pace::Normal => clicker.click_delay(),
+            Pace::Max => self.config.max_delay(),
+        }
+    }
+
+    /// Moves to the pace `buffs` call for, logging any change. `None` is a buff bar
+    /// that isn't read, which keeps the usual cadence.
+    pub fn update(&mut self, buffs: Option<&[ActiveBuff]>) -> Step {
+        let stack = buffs.map(stack);
+        let pace = match stack {
+            None => Pace::Normal,
+            Some(stack) if stack >= self.config.threshold => Pace::Max,
+            Some(stack) if stack > 1.0 => Pace::Normal,
+            Some(_) => Pace::Idle,
+        };
+
+        let mut cast = None;
+        if pace == Pace::Max && !self.cast {
+            self.cast = true;
+            cast = self.config.fthof_spell.filter(|_| self.config.cast_fthof);
+        } else if pace != Pace::Max {
+            self.cast = false;
+        }
+
+        if pace != self.pace {
+            match buffs {
+                None => println!("can't see the buff bar: {}", pace),
+                Some([]) => println!("no buffs running: {}", pace),
+                Some(buffs) => {
+                    let names: Vec<String> = buffs.iter()
+                        .map(|active| format!("{} ({:.0?} left)", active.buff, active.remaining))
+                        .collect();
+                    println!("buffs x{} from {}: {}", stack.unwrap_or(1.0), names.join(", "), pace);
+                }
+            }
+            self.pace = pace;
+        }
+        Step { pace, cast }
+    }
+}
+
+/// Everything the running buffs multiply a click by.
+pub fn stack(buffs: &[ActiveBuff]) -> f64 {
+    buffs.iter().map(|active| active.buff.production() * active.buff.clicks()).product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::buffs::Buff;
+
+    fn running(buffs: &[Buff]) -> Vec<ActiveBuff> {
+        buffs.iter()
+            .map(|&buff| ActiveBuff { buff, left: 0.5, remaining: Duration::from_secs(30), position: (0, 1000) })
+            .collect()
+    }
+
+    fn engine(cast_fthof: bool) -> ComboEngine {
+        ComboEngine::new(ComboConfig { cast_fthof, fthof_spell: Some((400, 300)), ..ComboConfig::default() })
+    }
+
+    #[test]
+    fn stacks_multiply() {
+        assert_eq!(stack(&[]), 1.0);
+        assert_eq!(stack(&running(&[Buff::Frenzy])), 7.0);
+        assert_eq!(stack(&running(&[Buff::Frenzy, Buff::ClickFrenzy])), 7.0 * 777.0);
+        assert_eq!(stack(&running(&[Buff::Frenzy, Buff::Clot])), 3.5);
+    }
+
+    #[test]
+    fn paces_by_the_stack() {
+        let mut engine = engine(false);
+        assert_eq!(engine.update(None).pace, Pace::Normal);
+        assert_eq!(engine.update(Some(&[])).pace, Pace::Idle);
+        assert_eq!(engine.update(Some(&running(&[Buff::Clot]))).pace, Pace::Idle);
+        assert_eq!(engine.update(Some(&running(&[Buff::Frenzy]))).pace, Pace::Normal);
+        assert_eq!(engine.update(Some(&running(&[Buff::Frenzy, Buff::ClickFrenzy]))).pace, Pace::Max);
+    }
+
+    #[test]
+    fn delays_follow_the_pace() {
+        let clicker = ClickerConfig::default();
+        let mut engine = engine(false);
+        engine.update(Some(&[]));
+        assert_eq!(engine.click_delay(&clicker), ComboConfig::default().idle_delay());
+        engine.update(Some(&running(&[Buff::Dragonflight])));
+        assert_eq!(engine.click_delay(&clicker), ComboConfig::default().max_delay());
+        engine.update(None);
+        assert_eq!(engine.click_delay(&clicker), clicker.click_delay());
+    }
+
+    #[test]
+    fn casts_once_per_combo() {
+        let combo = running(&[Buff::Frenzy, Buff::ClickFrenzy]);
+        let mut engine = engine(true);
+        assert_eq!(engine.update(Some(&combo)).cast, Some((400, 300)));
+        assert_eq!(engine.update(Some(&combo)).cast, None);
+        engine.update(Some(&running(&[Buff::Frenzy])));
+        assert_eq!(engine.update(Some(&combo)).cast, Some((400, 300)));
+    }
+
+    #[test]
+    fn casts_nothing_unless_asked_to() {
+        let mut engine = engine(false);
+        assert_eq!(engine.update(Some(&running(&[Buff::Dragonflight]))).cast, None);
+    }
+}
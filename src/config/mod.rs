@@ -0,0 +1,293 @@
+//! Settings read from `cookie_monster.toml` at startup.
+//!
+//! Every section and every key is optional. Whatever is left out keeps the
+//! value the bot was tuned with on a 1920x1080 screen, so an empty file (or
+//! none at all) behaves exactly like the defaults below. Unknown keys are
+//! rejected rather than ignored, since a misspelt tolerance that silently does
+//! nothing is worse than a bot that refuses to start.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::building::Building;
+use crate::detect::TemplateDirs;
+use crate::detect::shimmer::WrathPolicy;
+use crate::layout::Layout;
+use crate::layout::profile::Profile;
+
+/// Where the config is looked for when no other path is given.
+pub const DEFAULT_PATH: &str = "cookie_monster.toml";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Skip calibration and use `layout` as it is.
+    pub fixed_layout: bool,
+    /// Name of a profile saved by the calibration wizard. Its layout replaces
+    /// `layout`, and calibration is skipped.
+    pub profile: Option<String>,
+    pub clicker: ClickerConfig,
+    pub combo: ComboConfig,
+    pub buildings: BuildingConfig,
+    pub upgrades: UpgradeConfig,
+    pub golden: GoldenConfig,
+    pub lumps: LumpConfig,
+    pub buffs: BuffConfig,
+    pub templates: TemplateDirs,
+    /// Coordinates to use when calibration is off or fails.
+    pub layout: Layout,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickerConfig {
+    /// Pause between two clicks on the big cookie, in microseconds.
+    pub click_delay_us: u64,
+}
+
+impl Default for ClickerConfig {
+    fn default() -> Self {
+        Self { click_delay_us: 1000 }
+    }
+}
+
+impl ClickerConfig {
+    pub fn click_delay(&self) -> Duration {
+        Duration::from_micros(self.click_delay_us)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComboConfig {
+    /// What the running buffs have to multiply a click by before the clicker
+    /// goes flat out.
+    pub threshold: f64,
+    /// Pause between two clicks while it does, in microseconds.
+    pub max_delay_us: u64,
+    /// Pause between two clicks while no buffs are running, in microseconds.
+    pub idle_delay_us: u64,
+    /// Cast Force the Hand of Fate when a stack crosses the threshold.
+    pub cast_fthof: bool,
+    /// Where the spell's icon is in the open grimoire. Nothing is cast without it.
+    pub fthof_spell: Option<(i32, i32)>,
+}
+
+impl Default for ComboConfig {
+    fn default() -> Self {
+        Self { threshold: 100.0, max_delay_us: 200, idle_delay_us: 20_000, cast_fthof: false, fthof_spell: None }
+    }
+}
+
+impl ComboConfig {
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_micros(self.max_delay_us)
+    }
+
+    pub fn idle_delay(&self) -> Duration {
+        Duration::from_micros(self.idle_delay_us)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildingConfig {
+    /// Time between two building purchase passes, in seconds.
+    pub interval_secs: u64,
+    /// How many times one pass goes back over the store after buying something.
+    pub max_passes: u32,
+    /// Colour of an affordable row's name.
+    pub row_colour: (u8, u8, u8),
+    /// How far off `row_colour` a probe pixel may be.
+    pub row_tolerance: u8,
+    /// Same for Cursor, whose name is thinner and picks up more of the background.
+    pub cursor_tolerance: u8,
+}
+
+impl Default for BuildingConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 10 * 60,
+            max_passes: 20,
+            row_colour: (255, 255, 255),
+            row_tolerance: 2,
+            cursor_tolerance: 10,
+        }
+    }
+}
+
+impl BuildingConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn tolerance(&self, building: Building) -> u8 {
+        match building {
+            Building::Cursor => self.cursor_tolerance,
+            _ => self.row_tolerance,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpgradeConfig {
+    /// Frame colour of an affordable upgrade in the tray.
+    pub colour: (u8, u8, u8),
+    /// How far off `colour` a slot pixel may be.
+    pub tolerance: u8,
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        Self { colour: (230, 190, 148), tolerance: 1 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoldenConfig {
+    /// Click golden cookies at all.
+    pub enabled: bool,
+    /// Longest a golden cookie may be on screen before it's clicked, in
+    /// milliseconds. The screen is looked at about this often.
+    pub reaction_ms: u64,
+    /// When to click wrath cookies too.
+    pub wrath: WrathPolicy,
+}
+
+impl Default for GoldenConfig {
+    fn default() -> Self {
+        Self { enabled: true, reaction_ms: 500, wrath: WrathPolicy::Never }
+    }
+}
+
+impl GoldenConfig {
+    pub fn reaction(&self) -> Duration {
+        Duration::from_millis(self.reaction_ms)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LumpConfig {
+    /// Harvest the sugar lump once it's ripe.
+    pub harvest: bool,
+}
+
+impl Default for LumpConfig {
+    fn default() -> Self {
+        Self { harvest: true }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuffConfig {
+    /// How much longer than usual buffs last, for upgrades like Get lucky that
+    /// lengthen them. Only the estimate of their time left depends on it.
+    pub duration_scale: f64,
+}
+
+impl Default for BuffConfig {
+    fn default() -> Self {
+        Self { duration_scale: 1.0 }
+    }
+}
+
+impl Config {
+    /// Reads and validates the config at `path`. A missing file gives the defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                println!("no {} found, using the defaults", path.display());
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+        };
+
+        let mut config: Self = toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        if let Some(name) = &config.profile {
+            config.layout = Profile::load(name)?.layout;
+        }
+        config.validate().with_context(|| format!("checking {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Catches values that parse but can't work.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.clicker.click_delay_us > 0, "clicker.click_delay_us must be above 0, or the clicker starves every other thread of the mouse");
+        ensure!(self.combo.threshold > 1.0, "combo.threshold must be above 1, or the clicker never idles");
+        ensure!(self.combo.max_delay_us > 0, "combo.max_delay_us must be above 0, or the clicker starves every other thread of the mouse");
+        ensure!(self.combo.idle_delay_us > 0, "combo.idle_delay_us must be above 0");
+        if self.combo.cast_fthof && self.combo.fthof_spell.is_none() {
+            bail!("combo.cast_fthof is set, but combo.fthof_spell doesn't say where the spell is");
+        }
+        ensure!(self.buildings.interval_secs > 0, "buildings.interval_secs must be above 0");
+        ensure!(self.buildings.max_passes > 0, "buildings.max_passes must be at least 1");
+        ensure!(self.golden.reaction_ms > 0, "golden.reaction_ms must be above 0");
+        ensure!(self.buffs.duration_scale > 0.0, "buffs.duration_scale must be above 0");
+
+        let layout = &self.layout;
+        ensure!(!layout.upgrade_slots.is_empty(), "layout.upgrade_slots can't be empty");
+        ensure!(layout.row_height > 0, "layout.row_height must be above 0");
+        ensure!(layout.store.width > 0 && layout.store.height > 0, "layout.store can't be empty");
+        ensure!(
+            layout.store.x <= layout.text_left && layout.text_left < layout.owned_left && layout.owned_left < layout.store.right(),
+            "layout.text_left ({}) and layout.owned_left ({}) must be in that order inside the store ({} to {})",
+            layout.text_left, layout.owned_left, layout.store.x, layout.store.right(),
+        );
+        if layout.visible_rows > Building::ALL.len() {
+            bail!("layout.visible_rows is {}, but there are only {} buildings", layout.visible_rows, Building::ALL.len());
+        }
+        for (building, x, y) in &layout.building_probes {
+            ensure!(*x >= 0 && *y >= 0, "layout.building_probes has {} at ({}, {}), off the screen", building, x, y);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = Config::default();
+        change(&mut config);
+        format!("{:#}", config.validate().unwrap_err())
+    }
+
+    #[test]
+    fn the_shipped_config_is_the_defaults() {
+        let config = Config::load(DEFAULT_PATH).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn an_empty_config_is_the_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[buildings]\nrow_tolerence = 3\n").is_err());
+    }
+
+    #[test]
+    fn rejects_values_that_cant_work() {
+        assert!(invalid(|config| config.clicker.click_delay_us = 0).contains("clicker.click_delay_us"));
+        assert!(invalid(|config| config.combo.threshold = 1.0).contains("combo.threshold"));
+        assert!(invalid(|config| config.combo.cast_fthof = true).contains("combo.fthof_spell"));
+        assert!(invalid(|config| config.buildings.max_passes = 0).contains("buildings.max_passes"));
+        assert!(invalid(|config| config.layout.upgrade_slots.clear()).contains("layout.upgrade_slots"));
+        assert!(invalid(|config| config.layout.owned_left = config.layout.text_left).contains("layout.text_left"));
+        assert!(invalid(|config| config.layout.visible_rows = 21).contains("only 20 buildings"));
+        assert!(invalid(|config| config.layout.building_probes[0].2 = -1).contains("Cursor at (1692, -1)"));
+    }
+}
@@ -0,0 +1,333 @@
+//! Run state, mouse ownership, the screen `Layout` and the latest `GameState`,
+//! shared between the bot's threads.
+//!
+//! The mouse sits behind a lock. Whoever wants to move it (a purchase pass,
+//! the auto clicker) has to take the lock first, so a click can never land in
+//! the middle of another thread's `move_to`. When a `MouseGuard` is dropped
+//! the cursor is put back on the big cookie, which is where the auto clicker
+//! expects to find it; the auto clicker itself goes through `click_cookie`,
+//! which leaves it there. Golden cookies can't wait for the auto clicker's next
+//! turn, so `take_mouse_first` makes everyone else in `take_mouse` stand back
+//! until the mouse is handed over. A thread that has to wait with the mouse,
+//! like a purchase pass waiting for the store to scroll, does it in `hold`,
+//! which lends the mouse to golden cookie clicks in the meantime.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::detect::GameState;
+use crate::input::{InputSink, Reason};
+use crate::layout::Layout;
+
+pub struct Controller {
+    running: AtomicBool,
+    /// Threads waiting in `take_mouse_first`, and in `hold` to get the mouse back.
+    waiting_first: AtomicUsize,
+    layout: Mutex<Layout>,
+    mouse: Mutex<Box<dyn InputSink + Send>>,
+    state: Mutex<GameState>,
+}
+
+impl Controller {
+    /// The cursor is parked on the layout's big cookie whenever nobody is using the mouse.
+    pub fn new(input: Box<dyn InputSink + Send>, layout: Layout) -> Arc<Self> {
+        let controller = Arc::new(Self {
+            running: AtomicBool::new(true),
+            waiting_first: AtomicUsize::new(0),
+            layout: Mutex::new(layout),
+            mouse: Mutex::new(input),
+            state: Mutex::new(GameState::default()),
+        });
+        // Park the cursor right away so the first auto click lands on the cookie.
+        drop(controller.take_mouse());
+        controller
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Flips the run state and returns the new one.
+    pub fn toggle_running(&self) -> bool {
+        !self.running.fetch_xor(true, Ordering::SeqCst)
+    }
+
+    /// Replaces the latest game state with what was just read off a frame.
+    pub fn publish(&self, state: GameState) {
+        *self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = state;
+    }
+
+    /// Where things currently are on screen.
+    pub fn layout(&self) -> Layout {
+        self.layout.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Replaces the layout, after a recalibration, and parks the cursor on the new
+    /// cookie position. Must not be called while holding the mouse.
+    pub fn set_layout(&self, layout: Layout) {
+        *self.layout.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = layout;
+        drop(self.take_mouse());
+    }
+
+    /// The most recently published game state.
+    pub fn state(&self) -> GameState {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Blocks until the mouse is free and nobody wants it first, and hands it over.
+    pub fn take_mouse(&self) -> MouseGuard<'_> {
+        loop {
+            while self.mouse_wanted_first() {
+                sleep(WAIT_SLICE);
+            }
+            let guard = self.lock_mouse();
+            // Someone may have started waiting first while this thread was blocked.
+            if !self.mouse_wanted_first() {
+                return guard;
+            }
+        }
+    }
+
+    /// Clicks wherever the cursor is parked, once `take_mouse` hands the mouse over.
+    /// The cursor hasn't moved, so it isn't parked again afterwards.
+    pub fn click_cookie(&self) {
+        let mut guard = self.take_mouse();
+        guard.park = false;
+        guard.click();
+    }
+
+    /// `take_mouse`, ahead of everyone waiting in `take_mouse`.
+    pub fn take_mouse_first(&self) -> MouseGuard<'_> {
+        self.waiting_first.fetch_add(1, Ordering::SeqCst);
+        let guard = self.lock_mouse();
+        self.waiting_first.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
+
+    /// Waits `duration` without giving up `guard`, except to `take_mouse_first`,
+    /// and returns it. Nobody else gets the mouse in between, so whatever the holder
+    /// left on screen, like a scrolled store, is still there when it carries on.
+    /// The cursor is back on the cookie if the mouse was lent out.
+    pub fn hold<'a>(&'a self, mut guard: MouseGuard<'a>, duration: Duration) -> MouseGuard<'a> {
+        let until = Instant::now() + duration;
+        loop {
+            if self.mouse_wanted_first() {
+                // Counting this thread as waiting first keeps `take_mouse` out until
+                // the mouse is back.
+                self.waiting_first.fetch_add(1, Ordering::SeqCst);
+                drop(guard);
+                while self.waiting_first.load(Ordering::SeqCst) > 1 {
+                    sleep(WAIT_SLICE);
+                }
+                guard = self.lock_mouse();
+                self.waiting_first.fetch_sub(1, Ordering::SeqCst);
+            }
+            let now = Instant::now();
+            if now >= until {
+                return guard;
+            }
+            sleep((until - now).min(WAIT_SLICE));
+        }
+    }
+
+    /// Whether anyone is waiting in `take_mouse_first`.
+    #[inline]
+    pub fn mouse_wanted_first(&self) -> bool {
+        self.waiting_first.load(Ordering::SeqCst) > 0
+    }
+
+    fn lock_mouse(&self) -> MouseGuard<'_> {
+        // A thread that panicked while holding the mouse can't have left anything
+        // worse than a misplaced cursor, which the guard fixes anyway.
+        let input = self.mouse.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let cookie = self.layout.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).big_cookie;
+        MouseGuard { input, cookie, park: true }
+    }
+}
+
+/// How often waiting threads look at who wants the mouse.
+const WAIT_SLICE: Duration = Duration::from_millis(5);
+
+/// Exclusive use of the mouse. Parks the cursor on the cookie when dropped.
+pub struct MouseGuard<'a> {
+    input: MutexGuard<'a, Box<dyn InputSink + Send>>,
+    cookie: (i32, i32),
+    /// Whether the cursor may have left the cookie and has to go back.
+    park: bool,
+}
+
+impl<'a> Deref for MouseGuard<'a> {
+    type Target = dyn InputSink + Send + 'static;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.input
+    }
+}
+
+impl<'a> DerefMut for MouseGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut **self.input
+    }
+}
+
+impl<'a> Drop for MouseGuard<'a> {
+    fn drop(&mut self) {
+        if !self.park {
+            return;
+        }
+        self.input.because(Reason::new("back to the big cookie"));
+        self.input.move_to(self.cookie.0, self.cookie.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::input::{Action, RecordingSink};
+
+    /// A `RecordingSink` the test can still read while the controller owns the mouse.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<RecordingSink>>);
+
+    impl Shared {
+        fn actions(&self) -> Vec<Action> {
+            self.0.lock().unwrap().actions().iter().map(|&(_, action)| action).collect()
+        }
+    }
+
+    impl InputSink for Shared {
+        fn move_to(&mut self, x: i32, y: i32) { self.0.lock().unwrap().move_to(x, y) }
+
+        fn click(&mut self) { self.0.lock().unwrap().click() }
+
+        fn scroll(&mut self, amount: i32) { self.0.lock().unwrap().scroll(amount) }
+    }
+
+    fn controller() -> (Arc<Controller>, Shared) {
+        let sink = Shared::default();
+        let controller = Controller::new(Box::new(sink.clone()), Layout::default());
+        sink.0.lock().unwrap().clear();
+        (controller, sink)
+    }
+
+    fn position(actions: &[Action], action: Action) -> usize {
+        actions.iter().position(|done| *done == action).unwrap_or_else(|| panic!("no {:?} in {:?}", action, actions))
+    }
+
+    const COOKIE: Action = Action::Click { x: 287, y: 417 };
+    const PARK: Action = Action::Move { x: 287, y: 417 };
+
+    #[test]
+    fn clicks_never_land_in_the_middle_of_a_purchase() {
+        let (controller, sink) = controller();
+        let clicker = {
+            let controller = controller.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    controller.click_cookie();
+                    sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        sleep(Duration::from_millis(5));
+        {
+            let mut mouse = controller.take_mouse();
+            mouse.move_to(1700, 300);
+            sleep(Duration::from_millis(20));
+            mouse.click();
+            mouse.move_to(1700, 400);
+            mouse.click();
+        }
+        clicker.join().unwrap();
+
+        let actions = sink.actions();
+        let start = position(&actions, Action::Move { x: 1700, y: 300 });
+        assert_eq!(actions[start..start + 5], [
+            Action::Move { x: 1700, y: 300 },
+            Action::Click { x: 1700, y: 300 },
+            Action::Move { x: 1700, y: 400 },
+            Action::Click { x: 1700, y: 400 },
+            PARK,
+        ]);
+        // The clicker never moves the cursor, so every other action is one of its clicks.
+        assert_eq!(actions.iter().filter(|&action| *action == COOKIE).count(), 50);
+        assert_eq!(actions.len(), 55);
+    }
+
+    #[test]
+    fn golden_cookies_go_ahead_of_a_waiting_clicker() {
+        let (controller, sink) = controller();
+        let mouse = controller.take_mouse();
+
+        let clicker = {
+            let controller = controller.clone();
+            thread::spawn(move || controller.click_cookie())
+        };
+        sleep(Duration::from_millis(20));
+        let golden = {
+            let controller = controller.clone();
+            thread::spawn(move || {
+                let mut mouse = controller.take_mouse_first();
+                mouse.move_to(500, 600);
+                mouse.click();
+            })
+        };
+        sleep(Duration::from_millis(20));
+        drop(mouse);
+        golden.join().unwrap();
+        clicker.join().unwrap();
+
+        let actions = sink.actions();
+        assert!(position(&actions, Action::Click { x: 500, y: 600 }) < position(&actions, COOKIE), "{:?}", actions);
+    }
+
+    #[test]
+    fn holding_lends_the_mouse_to_golden_cookies_only() {
+        let (controller, sink) = controller();
+        let mut mouse = controller.take_mouse();
+        mouse.move_to(1760, 500);
+        mouse.scroll(15);
+
+        let clicker = {
+            let controller = controller.clone();
+            thread::spawn(move || controller.click_cookie())
+        };
+        let golden = {
+            let controller = controller.clone();
+            thread::spawn(move || {
+                sleep(Duration::from_millis(20));
+                let mut mouse = controller.take_mouse_first();
+                mouse.move_to(500, 600);
+                mouse.click();
+            })
+        };
+        let mut mouse = controller.hold(mouse, Duration::from_millis(200));
+        mouse.move_to(1760, 700);
+        mouse.click();
+        drop(mouse);
+        golden.join().unwrap();
+        clicker.join().unwrap();
+
+        let actions = sink.actions();
+        let golden = position(&actions, Action::Click { x: 500, y: 600 });
+        let bought = position(&actions, Action::Click { x: 1760, y: 700 });
+        assert!(golden < bought, "{:?}", actions);
+        assert!(position(&actions, COOKIE) > bought, "{:?}", actions);
+    }
+
+    #[test]
+    fn the_clicker_leaves_the_cursor_where_it_is() {
+        let (controller, sink) = controller();
+        controller.click_cookie();
+        controller.click_cookie();
+        assert_eq!(sink.actions(), vec![COOKIE, COOKIE]);
+
+        drop(controller.take_mouse());
+        assert_eq!(sink.actions(), vec![COOKIE, COOKIE, PARK]);
+    }
+}
@@ -0,0 +1,336 @@
+//! The buff bar at the bottom of the left panel, where the game shows an icon
+//! for every buff that's running.
+//!
+//! Each buff is recognised by a template of its icon, a PNG named after the buff
+//! (`frenzy.png`, `click_frenzy.png`, ...) in the buff template directory. Buffs
+//! without a template are never seen.
+//!
+//! While a buff runs, a dark pie sweeps over its icon as the time runs out, so
+//! a template pixel can turn up either as it is or shaded. The share of the icon
+//! still lit is the share of the buff's time left.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use crate::layout::Layout;
+use crate::my_screenshot::{Region, Screenshot};
+use super::{clamp, resized};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Buff {
+    /// Production x7.
+    Frenzy,
+    /// Clicks x777.
+    ClickFrenzy,
+    /// Clicks x1111.
+    Dragonflight,
+    /// Production x666.
+    ElderFrenzy,
+    /// Production halved.
+    Clot,
+}
+
+impl Buff {
+    pub const ALL: [Buff; 5] = [Buff::Frenzy, Buff::ClickFrenzy, Buff::Dragonflight, Buff::ElderFrenzy, Buff::Clot];
+
+    /// Name of the icon template, without the extension.
+    pub fn file_stem(&self) -> &'static str {
+        match self {
+            Buff::Frenzy => "frenzy",
+            Buff::ClickFrenzy => "click_frenzy",
+            Buff::Dragonflight => "dragonflight",
+            Buff::ElderFrenzy => "elder_frenzy",
+            Buff::Clot => "clot",
+        }
+    }
+
+    /// What the buff multiplies production by.
+    pub fn production(&self) -> f64 {
+        match self {
+            Buff::Frenzy => 7.0,
+            Buff::ElderFrenzy => 666.0,
+            Buff::Clot => 0.5,
+            Buff::ClickFrenzy | Buff::Dragonflight => 1.0,
+        }
+    }
+
+    /// What the buff multiplies the big cookie's clicks by, on top of production.
+    pub fn clicks(&self) -> f64 {
+        match self {
+            Buff::ClickFrenzy => 777.0,
+            Buff::Dragonflight => 1111.0,
+            _ => 1.0,
+        }
+    }
+
+    /// How long the buff lasts without any upgrades that lengthen it.
+    pub fn base_duration(&self) -> Duration {
+        Duration::from_secs(match self {
+            Buff::Frenzy => 77,
+            Buff::ClickFrenzy => 13,
+            Buff::Dragonflight => 10,
+            Buff::ElderFrenzy => 6,
+            Buff::Clot => 66,
+        })
+    }
+}
+
+impl fmt::Display for Buff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Buff::Frenzy => write!(f, "Frenzy"),
+            Buff::ClickFrenzy => write!(f, "Click frenzy"),
+            Buff::Dragonflight => write!(f, "Dragonflight"),
+            Buff::ElderFrenzy => write!(f, "Elder frenzy"),
+            Buff::Clot => write!(f, "Clot"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActiveBuff {
+    pub buff: Buff,
+    /// Share of the timer still to run, from 1 down to 0.
+    pub left: f64,
+    /// `left` of the buff's duration.
+    pub remaining: Duration,
+    /// Top left corner of the icon.
+    pub position: (usize, usize),
+}
+
+/// An icon template at 100% zoom.
+struct Icon {
+    buff: Buff,
+    image: RgbaImage,
+}
+
+/// An icon scaled for the current zoom, as the pixels that take part in matching.
+struct ScaledIcon {
+    buff: Buff,
+    width: usize,
+    height: usize,
+    pixels: Vec<(usize, usize, (u8, u8, u8))>,
+    /// Every `SPARSE`th pixel, checked first to rule out most positions cheaply.
+    sparse: Vec<(usize, usize, (u8, u8, u8))>,
+}
+
+const SPARSE: usize = 37;
+
+pub struct BuffDetector {
+    icons: Vec<Icon>,
+    /// `icons` scaled for the last scale they were read at, which only changes
+    /// with the layout.
+    scaled: Mutex<Option<(f64, Vec<ScaledIcon>)>>,
+    /// How far off the template a pixel may be, per channel.
+    pub tolerance: u8,
+    /// How much of the icon the pie leaves lit where it covers it.
+    pub shade: f64,
+    /// Share of an icon's pixels that have to match, lit or shaded.
+    pub min_match: f64,
+    /// Upgrades like Get lucky make buffs last longer than `Buff::base_duration`
+    /// by this much.
+    pub duration_scale: f64,
+}
+
+impl BuffDetector {
+    /// Loads the icon of every buff that has one in `dir`. Fails if none do.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut icons = vec![];
+        for buff in Buff::ALL {
+            let path = dir.join(format!("{}.png", buff.file_stem()));
+            if !path.exists() {
+                continue;
+            }
+            let image = image::open(&path)
+                .with_context(|| format!("can't load buff icon {}", path.display()))?
+                .to_rgba8();
+            icons.push(Icon { buff, image });
+        }
+        if icons.is_empty() {
+            bail!("no buff icons in {}", dir.display());
+        }
+
+        Ok(Self { icons, scaled: Mutex::new(None), tolerance: 24, shade: 0.5, min_match: 0.8, duration_scale: 1.0 })
+    }
+
+    /// Every buff showing in the layout's buff bar, left to right.
+    pub fn read(&self, screenshot: &Screenshot, layout: &Layout) -> Vec<ActiveBuff> {
+        let bar = clamp(screenshot, layout.buffs);
+        let scale_to = layout.scale();
+        let mut scaled = self.scaled.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let icons = match &*scaled {
+            Some((at, icons)) if *at == scale_to => icons,
+            _ => &scaled.insert((scale_to, self.icons.iter().map(|icon| scale(icon, scale_to)).collect())).1,
+        };
+        let mut found: Vec<ActiveBuff> = icons.iter()
+            .filter_map(|icon| self.find(screenshot, bar, icon))
+            .collect();
+        found.sort_by_key(|buff| buff.position.0);
+        found
+    }
+
+    /// The best match for `icon` in `bar`, if it's good enough.
+    fn find(&self, screenshot: &Screenshot, bar: Region, icon: &ScaledIcon) -> Option<ActiveBuff> {
+        if icon.width > bar.width || icon.height > bar.height {
+            return None;
+        }
+        let sparse_needed = self.min_match * icon.sparse.len() as f64 * 0.8;
+        let needed = self.min_match * icon.pixels.len() as f64;
+
+        let mut best: Option<(usize, usize, (usize, usize))> = None;
+        for y in bar.y..=bar.bottom() - icon.height {
+            for x in bar.x..=bar.right() - icon.width {
+                let (lit, shaded, either) = self.compare(screenshot, (x, y), &icon.sparse);
+                if ((lit + shaded + either) as f64) < sparse_needed {
+                    continue;
+                }
+                let (lit, shaded, either) = self.compare(screenshot, (x, y), &icon.pixels);
+                if ((lit + shaded + either) as f64) < needed {
+                    continue;
+                }
+                if best.is_none_or(|(best_lit, best_shaded, _)| lit + shaded > best_lit + best_shaded) {
+                    best = Some((lit, shaded, (x, y)));
+                }
+            }
+        }
+
+        let (lit, shaded, position) = best?;
+        let left = if lit + shaded == 0 { 1.0 } else { lit as f64 / (lit + shaded) as f64 };
+        let full = icon.buff.base_duration().as_secs_f64() * self.duration_scale;
+        Some(ActiveBuff {
+            buff: icon.buff,
+            left,
+            remaining: Duration::from_secs_f64(full * left),
+            position,
+        })
+    }
+
+    /// Counts the `pixels` at `at` that match as they are, shaded, and either
+    /// (dark template pixels look the same under the pie).
+    fn compare(&self, screenshot: &Screenshot, at: (usize, usize), pixels: &[(usize, usize, (u8, u8, u8))]) -> (usize, usize, usize) {
+        let close = |(r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)| {
+            r1.abs_diff(r2) <= self.tolerance && g1.abs_diff(g2) <= self.tolerance && b1.abs_diff(b2) <= self.tolerance
+        };
+        let shade = |channel: u8| (channel as f64 * self.shade).round() as u8;
+
+        let (mut lit, mut shaded, mut either) = (0, 0, 0);
+        for &(dx, dy, colour) in pixels {
+            let seen = match screenshot.pixel(at.0 + dx, at.1 + dy) {
+                Some(pixel) => pixel.rgb(),
+                None => continue,
+            };
+            let as_is = close(seen, colour);
+            let under_pie = close(seen, (shade(colour.0), shade(colour.1), shade(colour.2)));
+            match (as_is, under_pie) {
+                (true, true) => either += 1,
+                (true, false) => lit += 1,
+                (false, true) => shaded += 1,
+                (false, false) => {}
+            }
+        }
+        (lit, shaded, either)
+    }
+}
+
+/// `icon` resized for `scale`, with its transparent pixels left out.
+fn scale(icon: &Icon, scale: f64) -> ScaledIcon {
+    let image = resized(&icon.image, scale);
+
+    let pixels: Vec<(usize, usize, (u8, u8, u8))> = image.enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[3] == 255)
+        .map(|(x, y, pixel)| (x as usize, y as usize, (pixel[0], pixel[1], pixel[2])))
+        .collect();
+    let sparse = pixels.iter().step_by(SPARSE).copied().collect();
+    ScaledIcon { buff: icon.buff, width: image.width() as usize, height: image.height() as usize, pixels, sparse }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BACKGROUND: Rgba<u8> = Rgba([0, 0, 60, 255]);
+
+    /// A `size` square icon of two colours in `block` sized squares.
+    fn checkers(size: u32, block: u32, a: [u8; 3], b: [u8; 3]) -> RgbaImage {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let [r, g, bl] = if (x / block + y / block).is_multiple_of(2) { a } else { b };
+            Rgba([r, g, bl, 255])
+        })
+    }
+
+    fn frenzy(size: u32) -> RgbaImage {
+        checkers(size, size / 6, [220, 60, 60], [60, 220, 60])
+    }
+
+    fn click_frenzy(size: u32) -> RgbaImage {
+        checkers(size, size / 4, [60, 60, 230], [240, 240, 90])
+    }
+
+    fn detector() -> BuffDetector {
+        let icons = vec![Icon { buff: Buff::Frenzy, image: frenzy(48) }, Icon { buff: Buff::ClickFrenzy, image: click_frenzy(48) }];
+        BuffDetector { icons, scaled: Mutex::new(None), tolerance: 24, shade: 0.5, min_match: 0.8, duration_scale: 1.0 }
+    }
+
+    /// A 1080p frame with `icon` at `at`, its columns from `shaded_from` on under the pie.
+    fn paint(frame: &mut RgbaImage, icon: &RgbaImage, at: (u32, u32), shaded_from: u32) {
+        for (x, y, pixel) in icon.enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            let shade = |channel: u8| if x >= shaded_from { (channel as f64 * 0.5).round() as u8 } else { channel };
+            frame.put_pixel(at.0 + x, at.1 + y, Rgba([shade(r), shade(g), shade(b), a]));
+        }
+    }
+
+    #[test]
+    fn reads_the_icons_and_how_much_of_them_is_lit() {
+        let mut frame = RgbaImage::from_pixel(1920, 1080, BACKGROUND);
+        paint(&mut frame, &click_frenzy(48), (20, 1010), 48);
+        paint(&mut frame, &frenzy(48), (90, 1010), 36);
+        let found = detector().read(&Screenshot::from(&frame), &Layout::default());
+
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!((found[0].buff, found[0].position, found[0].left), (Buff::ClickFrenzy, (20, 1010), 1.0));
+        assert_eq!(found[0].remaining, Duration::from_secs(13));
+        assert_eq!((found[1].buff, found[1].position), (Buff::Frenzy, (90, 1010)));
+        assert!((found[1].left - 0.75).abs() < 0.02, "{:?}", found[1]);
+        assert!((found[1].remaining.as_secs_f64() - 77.0 * 0.75).abs() < 1.5, "{:?}", found[1]);
+    }
+
+    #[test]
+    fn sees_nothing_in_an_empty_bar() {
+        let frame = RgbaImage::from_pixel(1920, 1080, BACKGROUND);
+        assert!(detector().read(&Screenshot::from(&frame), &Layout::default()).is_empty());
+    }
+
+    #[test]
+    fn reads_half_size_icons_and_keeps_them_scaled() {
+        let detector = detector();
+        let half = Layout { row_height: 32, ..Layout::default() };
+        let mut frame = RgbaImage::from_pixel(960, 1080, BACKGROUND);
+        paint(&mut frame, &frenzy(24), (40, 1020), 12);
+        let frame = Screenshot::from(&frame);
+
+        let found = detector.read(&frame, &half);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!((found[0].buff, found[0].position), (Buff::Frenzy, (40, 1020)));
+        assert!((found[0].left - 0.5).abs() < 0.1, "{:?}", found[0]);
+
+        let cached = |detector: &BuffDetector| {
+            let scaled = detector.scaled.lock().unwrap();
+            let (at, icons) = scaled.as_ref().unwrap();
+            (*at, icons[0].width, icons.as_ptr() as usize)
+        };
+        let first = cached(&detector);
+        assert_eq!((first.0, first.1), (0.5, 24));
+        detector.read(&frame, &half);
+        assert_eq!(cached(&detector), first);
+
+        detector.read(&frame, &Layout::default());
+        let (at, width, _) = cached(&detector);
+        assert_eq!((at, width), (1.0, 48));
+    }
+}
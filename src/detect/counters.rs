@@ -0,0 +1,162 @@
+//! The bank counter at the top of the left panel and the "per second: N" line
+//! right under it.
+//!
+//! The bank is searched for in the band of the panel the `Layout` gives. The per second line
+//! is then looked for relative to wherever the bank digits turned up, since
+//! both are centred in the panel and move together when the number grows.
+
+use std::path::Path;
+use anyhow::{Context, Result};
+use crate::layout::Layout;
+use crate::my_screenshot::{Region, Screenshot};
+use crate::ocr::{DigitReader, Reading};
+use super::found;
+
+/// How far below the bank's baseline the per second line can sit.
+const CPS_LINE_DEPTH: usize = 40;
+
+pub struct CounterDetector {
+    bank_reader: DigitReader,
+    /// `None` without per second templates, which leaves only the bank read.
+    cps_reader: Option<DigitReader>,
+}
+
+impl CounterDetector {
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(bank_templates: P, cps_templates: Option<Q>) -> Result<Self> {
+        let cps_reader = match cps_templates {
+            Some(templates) => Some(DigitReader::load(templates).context("per second templates")?),
+            None => {
+                println!("not reading cookies per second: no cps templates in [templates]");
+                None
+            }
+        };
+        Ok(Self {
+            bank_reader: DigitReader::load(bank_templates).context("bank counter templates")?,
+            cps_reader,
+        })
+    }
+
+    /// Reads the bank. `None` if no digits were found.
+    pub fn read_bank(&self, screenshot: &Screenshot, layout: &Layout) -> Option<Reading> {
+        found(self.bank_reader.read(screenshot, layout.bank))
+    }
+
+    /// Looks for the bank digits anywhere above the big cookie and returns a band
+    /// of the layout's bank height with them in it, baseline three quarters down.
+    pub fn locate_bank(&self, screenshot: &Screenshot, layout: &Layout) -> Option<Region> {
+        let search = Region::new(
+            layout.bank.x,
+            0,
+            layout.bank.width,
+            layout.big_cookie.1.max(0) as usize,
+        );
+        let bank = found(self.bank_reader.read(screenshot, search))?;
+        let baseline = bank.glyphs[0].y;
+        let y = baseline.saturating_sub(layout.bank.height * 3 / 4);
+        Some(Region::new(layout.bank.x, y, layout.bank.width, layout.bank.height))
+    }
+
+    /// Reads cookies per second from the line under `bank`.
+    pub fn read_cps(&self, screenshot: &Screenshot, bank: &Reading) -> Option<Reading> {
+        let cps_reader = self.cps_reader.as_ref()?;
+        let (left, right) = (bank.left()?, bank.right()?);
+        let baseline = bank.glyphs[0].y;
+
+        // "per second: " makes the line wider than the number in it, and both lines
+        // are centred, so look half a bank width further out on either side.
+        let margin = (right - left) / 2 + 40;
+        let x = left.saturating_sub(margin);
+        let region = Region::new(x, baseline + 2, right + margin - x, CPS_LINE_DEPTH);
+
+        found(cps_reader.read(screenshot, region))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use crate::detect::write_number;
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// The label's letters in a 3x5 block font. Drawn four times over they're taller
+    /// and wider than the digits, so any template that could fit in a letter gets the room to.
+    const LETTERS: [(char, [&str; 5]); 9] = [
+        ('p', ["###", "#.#", "###", "#..", "#.."]),
+        ('e', ["###", "#..", "###", "#..", "###"]),
+        ('r', ["###", "#..", "#..", "#..", "#.."]),
+        ('s', ["###", "#..", "###", "..#", "###"]),
+        ('c', ["###", "#..", "#..", "#..", "###"]),
+        ('o', ["###", "#.#", "#.#", "#.#", "###"]),
+        ('n', ["###", "#.#", "#.#", "#.#", "#.#"]),
+        ('d', ["..#", "..#", "###", "#.#", "###"]),
+        (':', [".", "#", ".", "#", "."]),
+    ];
+    const LETTER_SCALE: u32 = 4;
+
+    fn fill(frame: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32) {
+        for y in y..y + height {
+            for x in x..x + width {
+                frame.put_pixel(x, y, WHITE);
+            }
+        }
+    }
+
+    /// Writes `text` in white with its bottom on `baseline`, the label's letters in
+    /// the block font, and returns where it ends.
+    fn write(frame: &mut RgbaImage, text: &str, mut x: u32, baseline: u32) -> u32 {
+        for ch in text.chars() {
+            match ch {
+                ' ' => x += 3 * LETTER_SCALE,
+                _ if ch.is_ascii_digit() || ch == ',' || ch == '.' => {
+                    x = write_number(frame, &ch.to_string(), x, baseline, WHITE);
+                }
+                _ => {
+                    let (_, rows) = LETTERS.iter().find(|(letter, _)| *letter == ch).unwrap();
+                    let top = baseline + 1 - 5 * LETTER_SCALE;
+                    for (row, line) in rows.iter().enumerate() {
+                        for (column, _) in line.chars().enumerate().filter(|&(_, c)| c == '#') {
+                            fill(frame, x + column as u32 * LETTER_SCALE, top + row as u32 * LETTER_SCALE,
+                                 LETTER_SCALE, LETTER_SCALE);
+                        }
+                    }
+                    x += (rows[0].len() as u32 + 1) * LETTER_SCALE;
+                }
+            }
+        }
+        x
+    }
+
+    #[test]
+    fn reads_the_number_after_the_per_second_label() {
+        let detector = CounterDetector::load("cookie_numbers", Some("cookie_numbers")).unwrap();
+        let layout = Layout::default();
+        let mut frame = RgbaImage::from_pixel(575, 200, Rgba([40, 30, 20, 255]));
+        write(&mut frame, "123,456,789", 200, 110);
+        let label_end = write(&mut frame, "per second: ", 140, 140);
+        write(&mut frame, "1,234.5", label_end, 140);
+        let frame = Screenshot::from(&frame);
+
+        let bank = detector.read_bank(&frame, &layout).unwrap();
+        assert_eq!(bank.text, "123,456,789");
+        let cps = detector.read_cps(&frame, &bank).unwrap();
+        assert_eq!(cps.text, "1,234.5");
+        assert_eq!(cps.value, Some(1234.5));
+        // The label was inside the region that got read.
+        let margin = (bank.right().unwrap() - bank.left().unwrap()) / 2 + 40;
+        assert!(bank.left().unwrap() - margin < 140);
+    }
+
+    #[test]
+    fn reads_no_cps_without_templates() {
+        let detector = CounterDetector::load("cookie_numbers", None::<&str>).unwrap();
+        let mut frame = RgbaImage::from_pixel(575, 200, Rgba([40, 30, 20, 255]));
+        write(&mut frame, "123", 200, 110);
+        write(&mut frame, "45", 200, 140);
+        let frame = Screenshot::from(&frame);
+
+        let bank = detector.read_bank(&frame, &Layout::default()).unwrap();
+        assert_eq!(detector.read_cps(&frame, &bank), None);
+    }
+}
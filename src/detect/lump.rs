@@ -0,0 +1,270 @@
+//! The sugar lump at the top of the left panel, which grows for about a day
+//! before it can be harvested.
+//!
+//! The lump's stage is told from its sprite, by templates in the lump template
+//! directory named after the stage they show: `growing*.png` for every sprite
+//! of a lump that's still growing, `mature*.png` and `ripe*.png`. Whichever
+//! template matches best gives the stage. Unusual lumps (bifurcated, golden,
+//! meaty, caramelized) only show up if they have templates of their own.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use crate::layout::Layout;
+use crate::my_screenshot::{Region, Screenshot};
+use super::{clamp, resized};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LumpStage {
+    /// Harvesting it now would lose it.
+    Growing,
+    /// Harvesting it now only might give a lump.
+    Mature,
+    /// Harvesting it now gives a lump.
+    Ripe,
+}
+
+impl LumpStage {
+    pub const ALL: [LumpStage; 3] = [LumpStage::Growing, LumpStage::Mature, LumpStage::Ripe];
+
+    /// What the stage's template names start with.
+    pub fn file_stem(&self) -> &'static str {
+        match self {
+            LumpStage::Growing => "growing",
+            LumpStage::Mature => "mature",
+            LumpStage::Ripe => "ripe",
+        }
+    }
+}
+
+impl fmt::Display for LumpStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LumpStage::Growing => write!(f, "growing"),
+            LumpStage::Mature => write!(f, "mature"),
+            LumpStage::Ripe => write!(f, "ripe"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lump {
+    pub stage: LumpStage,
+    /// Middle of the sprite, where clicking harvests it.
+    pub centre: (i32, i32),
+    /// Share of the template's pixels that matched.
+    pub score: f64,
+}
+
+/// A sprite template at 100% zoom.
+struct Sprite {
+    stage: LumpStage,
+    image: RgbaImage,
+}
+
+/// A sprite scaled for the current zoom, as the pixels that take part in matching.
+struct ScaledSprite {
+    stage: LumpStage,
+    width: usize,
+    height: usize,
+    pixels: Vec<(usize, usize, (u8, u8, u8))>,
+    /// Every `SPARSE`th pixel, checked first to rule out most positions cheaply.
+    sparse: Vec<(usize, usize, (u8, u8, u8))>,
+}
+
+const SPARSE: usize = 29;
+
+pub struct LumpDetector {
+    sprites: Vec<Sprite>,
+    /// `sprites` scaled for the last scale they were read at, which only changes
+    /// with the layout.
+    scaled: Mutex<Option<(f64, Vec<ScaledSprite>)>>,
+    /// How far off the template a pixel may be, per channel.
+    pub tolerance: u8,
+    /// Share of a sprite's pixels that have to match.
+    pub min_match: f64,
+}
+
+impl LumpDetector {
+    /// Loads every stage's templates from `dir`. Fails without a ripe one, since
+    /// then nothing would ever be harvested.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir).with_context(|| format!("can't list lump templates in {}", dir.display()))?;
+        let mut sprites = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "png") {
+                continue;
+            }
+            let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            let stage = match LumpStage::ALL.into_iter().find(|stage| name.starts_with(stage.file_stem())) {
+                Some(stage) => stage,
+                None => continue,
+            };
+            let image = image::open(&path)
+                .with_context(|| format!("can't load lump template {}", path.display()))?
+                .to_rgba8();
+            sprites.push(Sprite { stage, image });
+        }
+        if !sprites.iter().any(|sprite| sprite.stage == LumpStage::Ripe) {
+            bail!("no ripe lump template (ripe*.png) in {}", dir.display());
+        }
+
+        Ok(Self { sprites, scaled: Mutex::new(None), tolerance: 16, min_match: 0.8 })
+    }
+
+    /// The lump in the layout's lump region, if a template matches well enough.
+    pub fn read(&self, screenshot: &Screenshot, layout: &Layout) -> Option<Lump> {
+        let region = clamp(screenshot, layout.lump);
+        let scale_to = layout.scale();
+        let mut scaled = self.scaled.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let sprites = match &*scaled {
+            Some((at, sprites)) if *at == scale_to => sprites,
+            _ => &scaled.insert((scale_to, self.sprites.iter().map(|sprite| scale(sprite, scale_to)).collect())).1,
+        };
+        sprites.iter()
+            .filter_map(|sprite| self.find(screenshot, region, sprite))
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+    }
+
+    fn find(&self, screenshot: &Screenshot, region: Region, sprite: &ScaledSprite) -> Option<Lump> {
+        let (width, height) = (sprite.width, sprite.height);
+        if width > region.width || height > region.height || sprite.pixels.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<(f64, (usize, usize))> = None;
+        for y in region.y..=region.bottom() - height {
+            for x in region.x..=region.right() - width {
+                if self.score(screenshot, (x, y), &sprite.sparse) < self.min_match * 0.8 {
+                    continue;
+                }
+                let score = self.score(screenshot, (x, y), &sprite.pixels);
+                if score >= self.min_match && best.is_none_or(|(best, _)| score > best) {
+                    best = Some((score, (x, y)));
+                }
+            }
+        }
+
+        let (score, (x, y)) = best?;
+        Some(Lump { stage: sprite.stage, centre: ((x + width / 2) as i32, (y + height / 2) as i32), score })
+    }
+
+    /// Share of `pixels` that match with the sprite's corner at `at`.
+    fn score(&self, screenshot: &Screenshot, at: (usize, usize), pixels: &[(usize, usize, (u8, u8, u8))]) -> f64 {
+        let tolerance = self.tolerance;
+        let matched = pixels.iter()
+            .filter(|&&(dx, dy, (r, g, b))| match screenshot.pixel(at.0 + dx, at.1 + dy) {
+                Some(pixel) => {
+                    let (sr, sg, sb) = pixel.rgb();
+                    sr.abs_diff(r) <= tolerance && sg.abs_diff(g) <= tolerance && sb.abs_diff(b) <= tolerance
+                }
+                None => false,
+            })
+            .count();
+        matched as f64 / pixels.len() as f64
+    }
+}
+
+/// `sprite` resized for `scale`, with its transparent pixels left out.
+fn scale(sprite: &Sprite, scale: f64) -> ScaledSprite {
+    let image = resized(&sprite.image, scale);
+
+    let pixels: Vec<(usize, usize, (u8, u8, u8))> = image.enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[3] == 255)
+        .map(|(x, y, pixel)| (x as usize, y as usize, (pixel[0], pixel[1], pixel[2])))
+        .collect();
+    let sparse = pixels.iter().step_by(SPARSE).copied().collect();
+    ScaledSprite { stage: sprite.stage, width: image.width() as usize, height: image.height() as usize, pixels, sparse }
+}
+
+/// Don't harvest again this soon after a harvest, in case a frame taken before
+/// the click still shows the ripe lump.
+const HARVEST_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Decides when to harvest, and keeps count of what's been harvested.
+pub struct Harvester {
+    enabled: bool,
+    stage: Option<LumpStage>,
+    last: Option<Instant>,
+    /// Lumps harvested since the bot started.
+    pub harvested: u32,
+}
+
+impl Harvester {
+    /// A disabled harvester only logs the lump's stage.
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, stage: None, last: None, harvested: 0 }
+    }
+
+    /// Whether to click `lump` now: only once it's ripe, never while it grows or
+    /// is merely mature, where harvesting can lose it. Logs stage changes.
+    pub fn should_harvest(&mut self, lump: &Lump) -> bool {
+        if self.stage != Some(lump.stage) {
+            println!("the sugar lump is {}", lump.stage);
+            self.stage = Some(lump.stage);
+        }
+        self.enabled
+            && lump.stage == LumpStage::Ripe
+            && self.last.is_none_or(|last| last.elapsed() >= HARVEST_COOLDOWN)
+    }
+
+    /// Records a harvest that was just clicked.
+    pub fn record(&mut self) {
+        self.wait();
+        self.harvested += 1;
+    }
+
+    /// Starts the cooldown without counting a harvest, after a click that only
+    /// went into a dry run's log.
+    pub fn wait(&mut self) {
+        self.last = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lump(stage: LumpStage) -> Lump {
+        Lump { stage, centre: (50, 80), score: 1.0 }
+    }
+
+    #[test]
+    fn harvests_only_ripe_lumps() {
+        let mut harvester = Harvester::new(true);
+        assert!(!harvester.should_harvest(&lump(LumpStage::Growing)));
+        assert!(!harvester.should_harvest(&lump(LumpStage::Mature)));
+        assert!(harvester.should_harvest(&lump(LumpStage::Ripe)));
+    }
+
+    #[test]
+    fn a_disabled_harvester_never_harvests() {
+        let mut harvester = Harvester::new(false);
+        assert!(!harvester.should_harvest(&lump(LumpStage::Ripe)));
+    }
+
+    #[test]
+    fn waits_out_the_cooldown() {
+        let mut harvester = Harvester::new(true);
+        harvester.record();
+        assert_eq!(harvester.harvested, 1);
+        assert!(!harvester.should_harvest(&lump(LumpStage::Ripe)));
+
+        harvester.last = Some(Instant::now() - HARVEST_COOLDOWN);
+        assert!(harvester.should_harvest(&lump(LumpStage::Ripe)));
+    }
+
+    #[test]
+    fn dry_runs_wait_without_counting() {
+        let mut harvester = Harvester::new(true);
+        harvester.wait();
+        assert_eq!(harvester.harvested, 0);
+        assert!(!harvester.should_harvest(&lump(LumpStage::Ripe)));
+    }
+}
@@ -0,0 +1,233 @@
+//! Detectors that turn a `Screenshot` into what the bot knows about the game.
+//!
+//! Each detector looks at one part of the screen. `Detector::observe` runs all
+//! of them on a frame and collects the results in a `GameState`, looking
+//! wherever the `Layout` says each part is.
+
+pub mod buffs;
+pub mod counters;
+pub mod lump;
+pub mod shimmer;
+pub mod store;
+
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::Result;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use crate::config::{BuffConfig, UpgradeConfig};
+use crate::layout::Layout;
+use crate::my_screenshot::Screenshot;
+use crate::notation::CookieAmount;
+use crate::ocr::{clamp, Reading};
+use self::buffs::{ActiveBuff, Buff, BuffDetector};
+use self::counters::CounterDetector;
+use self::lump::{Lump, LumpDetector};
+use self::shimmer::ShimmerDetector;
+use self::store::{StoreDetector, StoreRow};
+
+/// Everything read off a single frame. Fields are `None` or empty when their
+/// detector couldn't make sense of the frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameState {
+    /// Cookies in the bank.
+    pub bank: Option<CookieAmount>,
+    /// Cookies per second.
+    pub cps: Option<CookieAmount>,
+    /// Visible store rows, top to bottom.
+    pub store: Vec<StoreRow>,
+    /// Buffs running, left to right along the buff bar. `None` if the bar isn't read.
+    pub buffs: Option<Vec<ActiveBuff>>,
+    /// The sugar lump and how far it's grown.
+    pub lump: Option<Lump>,
+}
+
+impl GameState {
+    /// How long until the bank holds `price` at the current production.
+    /// `None` if either is unknown or nothing is being produced.
+    pub fn time_until(&self, price: CookieAmount) -> Option<Duration> {
+        let (bank, cps) = (self.bank?, self.cps?);
+        if bank >= price {
+            return Some(Duration::ZERO);
+        }
+        if cps <= CookieAmount::ZERO {
+            return None;
+        }
+        Duration::try_from_secs_f64((price - bank).ratio(cps)).ok()
+    }
+
+    /// Whether `buff` is running. `None` if the buff bar isn't read.
+    pub fn is_active(&self, buff: Buff) -> Option<bool> {
+        Some(self.buffs.as_ref()?.iter().any(|active| active.buff == buff))
+    }
+}
+
+/// Where each detector finds its digit templates. Only the bank's ship with the
+/// bot; the others are cropped from a frame of the game, as `cookie_monster.toml`
+/// explains, and are `None` until then.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateDirs {
+    /// The bank counter.
+    pub bank: PathBuf,
+    /// The "per second" line under the bank.
+    pub cps: Option<PathBuf>,
+    /// Store prices. The store is only read with these and `owned` both set.
+    pub price: Option<PathBuf>,
+    /// Owned counts on the right of the store rows.
+    pub owned: Option<PathBuf>,
+    /// Buff icons, one PNG per buff. Combos and Clot need these.
+    pub buffs: Option<PathBuf>,
+    /// Sugar lump sprites, named after their stage. The harvester needs these.
+    pub lump: Option<PathBuf>,
+}
+
+impl Default for TemplateDirs {
+    fn default() -> Self {
+        Self {
+            bank: PathBuf::from("cookie_numbers/"),
+            cps: None,
+            price: None,
+            owned: None,
+            buffs: None,
+            lump: None,
+        }
+    }
+}
+
+/// All detectors. One whose templates are missing is left out, and the parts of
+/// `GameState` it fills in stay empty.
+pub struct Detector {
+    pub counters: Option<CounterDetector>,
+    pub store: Option<StoreDetector>,
+    pub buffs: Option<BuffDetector>,
+    pub lump: Option<LumpDetector>,
+    /// Golden and wrath cookies are looked for on their own thread, more often
+    /// than `observe` runs, so they aren't part of `GameState`.
+    pub shimmers: ShimmerDetector,
+}
+
+impl Detector {
+    pub fn load(dirs: &TemplateDirs, buffs: &BuffConfig) -> Self {
+        let counters = CounterDetector::load(&dirs.bank, dirs.cps.as_ref())
+            .map_err(|err| println!("not reading the counters: {:#}", err))
+            .ok();
+        let store = match (&dirs.price, &dirs.owned) {
+            (Some(price), Some(owned)) => StoreDetector::load(price, owned)
+                .map_err(|err| println!("not reading the store: {:#}", err))
+                .ok(),
+            _ => {
+                println!("not reading the store: no price and owned templates in [templates]");
+                None
+            }
+        };
+        let buffs = match &dirs.buffs {
+            Some(dir) => BuffDetector::load(dir)
+                .map(|mut detector| {
+                    detector.duration_scale = buffs.duration_scale;
+                    detector
+                })
+                .map_err(|err| println!("not reading the buff bar: {:#}", err))
+                .ok(),
+            None => {
+                println!("not reading the buff bar: no buff icons in [templates]");
+                None
+            }
+        };
+        let lump = match &dirs.lump {
+            Some(dir) => LumpDetector::load(dir)
+                .map_err(|err| println!("not looking at the sugar lump: {:#}", err))
+                .ok(),
+            None => {
+                println!("not looking at the sugar lump: no lump sprites in [templates]");
+                None
+            }
+        };
+
+        Self { counters, store, buffs, lump, shimmers: ShimmerDetector::default() }
+    }
+
+    /// `Layout::calibrate`, with the bank band then moved to wherever the bank's
+    /// digits match their templates, if they do.
+    pub fn calibrate(&self, screenshot: &Screenshot, upgrades: &UpgradeConfig) -> Result<Layout> {
+        let mut layout = Layout::calibrate(screenshot, upgrades)?;
+        if let Some(bank) = self.counters.as_ref().and_then(|counters| counters.locate_bank(screenshot, &layout)) {
+            layout.bank = bank;
+        }
+        Ok(layout)
+    }
+
+    pub fn observe(&self, screenshot: &Screenshot, layout: &Layout) -> GameState {
+        let mut state = GameState::default();
+
+        if let Some(counters) = &self.counters {
+            let bank = counters.read_bank(screenshot, layout);
+            let cps = bank.as_ref().and_then(|bank| counters.read_cps(screenshot, bank));
+            state.bank = bank.and_then(|reading| reading.amount());
+            state.cps = cps.and_then(|reading| reading.amount());
+        }
+        if let Some(store) = &self.store {
+            state.store = store.read_rows(screenshot, layout);
+        }
+        if let Some(buffs) = &self.buffs {
+            state.buffs = Some(buffs.read(screenshot, layout));
+        }
+        if let Some(lump) = &self.lump {
+            state.lump = lump.read(screenshot, layout);
+        }
+
+        state
+    }
+}
+
+/// `None` for a reading that didn't find any digits.
+fn found(reading: Reading) -> Option<Reading> {
+    if reading.glyphs.is_empty() { None } else { Some(reading) }
+}
+
+/// A template taken at 100% zoom, resized for `scale`.
+fn resized(image: &RgbaImage, scale: f64) -> RgbaImage {
+    if (scale - 1.0).abs() < 0.01 {
+        return image.clone();
+    }
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+    imageops::resize(image, width, height, FilterType::Triangle)
+}
+
+/// Writes `text`, digits and separators, in `colour` with its bottom on `baseline`
+/// in the templates of `cookie_numbers/`. Returns where it ends.
+#[cfg(test)]
+fn write_number(frame: &mut RgbaImage, text: &str, mut x: u32, baseline: u32, colour: image::Rgba<u8>) -> u32 {
+    let mut fill = |x: u32, y: u32, width: u32, height: u32| {
+        for y in y..y + height {
+            for x in x..x + width {
+                frame.put_pixel(x, y, colour);
+            }
+        }
+    };
+    for ch in text.chars() {
+        match ch {
+            ',' => {
+                fill(x + 1, baseline - 2, 3, 6);
+                x += 6;
+            }
+            '.' => {
+                fill(x + 1, baseline - 2, 3, 3);
+                x += 6;
+            }
+            _ => {
+                let digit = image::open(format!("cookie_numbers/{}.png", ch)).unwrap().to_rgba8();
+                let top = baseline + 1 - digit.height();
+                for (dx, dy, pixel) in digit.enumerate_pixels() {
+                    if pixel.0 == [0, 0, 0, 255] {
+                        fill(x + dx, top + dy, 1, 1);
+                    }
+                }
+                x += digit.width() + 2;
+            }
+        }
+    }
+    x
+}
@@ -0,0 +1,363 @@
+//! Golden cookies, which the game calls shimmers: a cookie that fades in
+//! somewhere on the screen, stays for a few seconds and does something when
+//! clicked. During the Grandmapocalypse some of them are red wrath cookies,
+//! whose outcomes include harmful ones like Clot and Ruin.
+//!
+//! The screen is sampled on a coarse grid for gold and red pixels. Neighbouring
+//! samples of either colour are grouped into blobs, and a blob counts as a
+//! shimmer if it has the cookie's shape: about as wide as it is tall, the right
+//! size for the zoom, and filled out like a disc rather than a line of text or a
+//! frame. Whichever hue most of it is decides the kind. Nothing inside the
+//! store panel is looked at, since upgrade icons and prices there can be gold
+//! or red too.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::layout::Layout;
+use crate::my_screenshot::Screenshot;
+
+/// Sprite diameter at 100% zoom, not counting the glow around it.
+const DIAMETER: f64 = 96.0;
+/// How far apart pixels are sampled at 100% zoom.
+const STEP: f64 = 3.0;
+/// Hues of a golden cookie, in degrees: yellows through golden oranges. The big
+/// cookie's dough, at about 30, is just below.
+const GOLD_HUES: (f64, f64) = (35.0, 60.0);
+/// How far a wrath cookie's hue may be from pure red, either way, in degrees.
+const WRATH_HUE: f64 = 15.0;
+/// Least saturation of either kind. The upgrade frames' pale tan is below it.
+const MIN_SATURATION: f64 = 0.4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShimmerKind {
+    Golden,
+    Wrath,
+}
+
+impl fmt::Display for ShimmerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShimmerKind::Golden => write!(f, "golden cookie"),
+            ShimmerKind::Wrath => write!(f, "wrath cookie"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shimmer {
+    pub kind: ShimmerKind,
+    /// Where to click it.
+    pub centre: (i32, i32),
+    pub diameter: usize,
+    /// Colour at the centre when it was found.
+    pub colour: (u8, u8, u8),
+}
+
+#[derive(Clone, Debug)]
+pub struct ShimmerDetector {
+    /// Smallest and largest diameter accepted, as a share of `DIAMETER`. The
+    /// cookie pulses while it's up and grows from nothing as it fades in.
+    pub size_range: (f64, f64),
+    /// Share of a blob's bounding box that has to be gold or red. A disc fills
+    /// about 0.79 of its box; chocolate chips and shading take some of that away.
+    pub min_fill: f64,
+}
+
+impl Default for ShimmerDetector {
+    fn default() -> Self {
+        Self { size_range: (0.5, 1.5), min_fill: 0.45 }
+    }
+}
+
+impl ShimmerDetector {
+    /// Every shimmer on screen, top to bottom.
+    pub fn find(&self, screenshot: &Screenshot, layout: &Layout) -> Vec<Shimmer> {
+        let scale = layout.scale();
+        let step = (STEP * scale).round().max(1.0) as usize;
+        let (columns, rows) = (screenshot.width() / step, screenshot.height() / step);
+
+        let in_store = |x: usize, y: usize| {
+            x >= layout.store.x && x < layout.store.right() && y >= layout.store.y && y < layout.store.bottom()
+        };
+        let cells: Vec<Option<ShimmerKind>> = (0..rows * columns)
+            .map(|i| {
+                let (x, y) = ((i % columns) * step, (i / columns) * step);
+                if in_store(x, y) {
+                    return None;
+                }
+                screenshot.pixel(x, y).and_then(|pixel| classify(pixel.rgb()))
+            })
+            .collect();
+
+        let (min, max) = (DIAMETER * scale * self.size_range.0, DIAMETER * scale * self.size_range.1);
+        let mut seen = vec![false; cells.len()];
+        let mut cookies = vec![];
+        for start in 0..cells.len() {
+            if cells[start].is_none() || seen[start] {
+                continue;
+            }
+            let blob = Blob::flood(start, columns, &cells, &mut seen);
+            let (width, height) = ((blob.right - blob.left + 1) * step, (blob.bottom - blob.top + 1) * step);
+            let fill = blob.count as f64 / ((blob.right - blob.left + 1) * (blob.bottom - blob.top + 1)) as f64;
+            let round = width.max(height) as f64 <= 1.35 * width.min(height) as f64;
+            if !round || fill < self.min_fill || (width as f64) < min || (width as f64) > max {
+                continue;
+            }
+
+            let centre = ((blob.left + blob.right) * step / 2, (blob.top + blob.bottom) * step / 2);
+            let colour = screenshot.pixel(centre.0, centre.1).map_or((0, 0, 0), |pixel| pixel.rgb());
+            cookies.push(Shimmer {
+                kind: if blob.wrath * 2 > blob.count { ShimmerKind::Wrath } else { ShimmerKind::Golden },
+                centre: (centre.0 as i32, centre.1 as i32),
+                diameter: (width + height) / 2,
+                colour,
+            });
+        }
+        cookies
+    }
+}
+
+/// Tells the kinds apart by hue. Gold is also bright; wrath cookies are a darker
+/// red, so they only need to be a little brighter than the background.
+fn classify(rgb: (u8, u8, u8)) -> Option<ShimmerKind> {
+    let (hue, saturation, value) = hsv(rgb);
+    if saturation < MIN_SATURATION {
+        None
+    } else if hue >= GOLD_HUES.0 && hue <= GOLD_HUES.1 && value >= 0.75 {
+        Some(ShimmerKind::Golden)
+    } else if (hue <= WRATH_HUE || hue >= 360.0 - WRATH_HUE) && value >= 0.5 {
+        Some(ShimmerKind::Wrath)
+    } else {
+        None
+    }
+}
+
+/// Hue in degrees from red, saturation and value from 0 to 1.
+fn hsv((r, g, b): (u8, u8, u8)) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let chroma = max - min;
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+    (hue, saturation, max)
+}
+
+/// Connected gold or red samples, in grid cells.
+struct Blob {
+    left: usize,
+    right: usize,
+    top: usize,
+    bottom: usize,
+    count: usize,
+    /// How many of them are red.
+    wrath: usize,
+}
+
+impl Blob {
+    /// Every shimmer coloured cell reachable from `start` through its four neighbours.
+    fn flood(start: usize, columns: usize, cells: &[Option<ShimmerKind>], seen: &mut [bool]) -> Blob {
+        let (x, y) = (start % columns, start / columns);
+        let mut blob = Blob { left: x, right: x, top: y, bottom: y, count: 0, wrath: 0 };
+        let mut stack = vec![start];
+        seen[start] = true;
+        while let Some(cell) = stack.pop() {
+            let (x, y) = (cell % columns, cell / columns);
+            blob.left = blob.left.min(x);
+            blob.right = blob.right.max(x);
+            blob.top = blob.top.min(y);
+            blob.bottom = blob.bottom.max(y);
+            blob.count += 1;
+            if cells[cell] == Some(ShimmerKind::Wrath) {
+                blob.wrath += 1;
+            }
+
+            let neighbours = [
+                (x > 0).then(|| cell - 1),
+                (x + 1 < columns).then(|| cell + 1),
+                (y > 0).then(|| cell - columns),
+                Some(cell + columns).filter(|&below| below < cells.len()),
+            ];
+            for next in neighbours.into_iter().flatten() {
+                if cells[next].is_some() && !seen[next] {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        blob
+    }
+}
+
+/// When to click a wrath cookie. Golden ones are always clicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WrathPolicy {
+    Always,
+    Never,
+    /// Only while Clot isn't active.
+    UnlessClot,
+}
+
+impl WrathPolicy {
+    /// Whether to click `kind`, and why. `clot` is whether Clot is active, or
+    /// `None` if that isn't known.
+    pub fn decide(self, kind: ShimmerKind, clot: Option<bool>) -> (bool, &'static str) {
+        match (kind, self, clot) {
+            (ShimmerKind::Golden, _, _) => (true, "golden cookies are always clicked"),
+            (ShimmerKind::Wrath, WrathPolicy::Always, _) => (true, "wrath cookies are always clicked"),
+            (ShimmerKind::Wrath, WrathPolicy::Never, _) => (false, "wrath cookies are never clicked"),
+            (ShimmerKind::Wrath, WrathPolicy::UnlessClot, Some(false)) => (true, "Clot isn't active"),
+            (ShimmerKind::Wrath, WrathPolicy::UnlessClot, Some(true)) => (false, "Clot is active"),
+            (ShimmerKind::Wrath, WrathPolicy::UnlessClot, None) => (false, "can't tell whether Clot is active"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    const BACKGROUND: Rgba<u8> = Rgba([20, 40, 90, 255]);
+    const GOLD: Rgba<u8> = Rgba([255, 200, 50, 255]);
+    const RED: Rgba<u8> = Rgba([200, 40, 30, 255]);
+
+    fn blank() -> RgbaImage {
+        RgbaImage::from_pixel(1920, 1080, BACKGROUND)
+    }
+
+    fn disc(image: &mut RgbaImage, (cx, cy): (i32, i32), radius: i32, colour: Rgba<u8>) {
+        for y in cy - radius..=cy + radius {
+            for x in cx - radius..=cx + radius {
+                if (x - cx).pow(2) + (y - cy).pow(2) <= radius * radius {
+                    image.put_pixel(x as u32, y as u32, colour);
+                }
+            }
+        }
+    }
+
+    fn find(image: &RgbaImage) -> Vec<Shimmer> {
+        ShimmerDetector::default().find(&Screenshot::from(image), &Layout::default())
+    }
+
+    fn near((x, y): (i32, i32), (to_x, to_y): (i32, i32)) -> bool {
+        (x - to_x).abs() <= 3 && (y - to_y).abs() <= 3
+    }
+
+    #[test]
+    fn finds_golden_and_wrath_cookies() {
+        let mut image = blank();
+        disc(&mut image, (900, 300), 48, GOLD);
+        disc(&mut image, (1200, 700), 48, RED);
+        let found = find(&image);
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!(found[0].kind, ShimmerKind::Golden);
+        assert!(near(found[0].centre, (900, 300)), "{:?}", found[0]);
+        assert!(found[0].diameter.abs_diff(96) <= 6, "{:?}", found[0]);
+        assert_eq!(found[0].colour, (255, 200, 50));
+        assert_eq!(found[1].kind, ShimmerKind::Wrath);
+        assert!(near(found[1].centre, (1200, 700)), "{:?}", found[1]);
+    }
+
+    #[test]
+    fn ignores_the_store() {
+        let mut image = blank();
+        disc(&mut image, (1760, 600), 48, GOLD);
+        assert!(find(&image).is_empty());
+    }
+
+    #[test]
+    fn rejects_text_frames_and_specks() {
+        let mut image = blank();
+        // A line of gold text.
+        for y in 200..216 {
+            for x in 300..600 {
+                image.put_pixel(x, y, GOLD);
+            }
+        }
+        // A gold frame the size of a cookie.
+        for y in 500..596 {
+            for x in 700..796 {
+                if !(706..790).contains(&x) || !(506..590).contains(&y) {
+                    image.put_pixel(x, y, GOLD);
+                }
+            }
+        }
+        // Far too small, and far too big.
+        disc(&mut image, (1000, 800), 10, GOLD);
+        disc(&mut image, (1200, 400), 120, GOLD);
+        assert!(find(&image).is_empty(), "{:?}", find(&image));
+    }
+
+    #[test]
+    fn follows_the_zoom() {
+        // At half size a 96 pixel disc is too big, and a 48 pixel one is right.
+        let layout = Layout { row_height: 32, ..Layout::default() };
+        let mut image = blank();
+        disc(&mut image, (400, 800), 48, GOLD);
+        disc(&mut image, (900, 300), 24, GOLD);
+        let found = ShimmerDetector::default().find(&Screenshot::from(&image), &layout);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(near(found[0].centre, (900, 300)), "{:?}", found[0]);
+    }
+
+    #[test]
+    fn classifies_by_hue() {
+        // Body, shine and rim of each sprite.
+        for gold in [(255, 200, 50), (255, 232, 130), (220, 165, 35)] {
+            assert_eq!(classify(gold), Some(ShimmerKind::Golden), "{:?}", gold);
+        }
+        for red in [(200, 40, 30), (255, 110, 90), (150, 25, 25), (190, 30, 60)] {
+            assert_eq!(classify(red), Some(ShimmerKind::Wrath), "{:?}", red);
+        }
+        // Dough, a chocolate chip, an upgrade frame, the background, white text,
+        // an orange just short of gold and a gold too dark to be lit.
+        for other in [(200, 130, 60), (90, 50, 30), (230, 190, 148), (20, 40, 90), (255, 255, 255),
+                      (255, 140, 0), (150, 115, 25)] {
+            assert_eq!(classify(other), None, "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn hue_boundaries() {
+        assert_eq!(hsv((255, 0, 0)).0, 0.0);
+        assert_eq!(hsv((255, 255, 0)).0, 60.0);
+        assert!((hsv((255, 149, 0)).0 - 35.0).abs() < 0.1);
+        assert!((hsv((255, 0, 64)).0 - 345.0).abs() < 0.1);
+        assert_eq!(classify((255, 150, 0)), Some(ShimmerKind::Golden));
+        assert_eq!(classify((255, 145, 0)), None);
+        assert_eq!(classify((255, 63, 0)), Some(ShimmerKind::Wrath));
+        assert_eq!(classify((255, 68, 0)), None);
+        assert_eq!(classify((255, 0, 63)), Some(ShimmerKind::Wrath));
+        assert_eq!(classify((255, 0, 68)), None);
+    }
+
+    #[test]
+    fn golden_cookies_are_clicked_whatever_the_policy() {
+        for policy in [WrathPolicy::Always, WrathPolicy::Never, WrathPolicy::UnlessClot] {
+            for clot in [None, Some(false), Some(true)] {
+                assert!(policy.decide(ShimmerKind::Golden, clot).0);
+            }
+        }
+    }
+
+    #[test]
+    fn wrath_cookies_follow_the_policy() {
+        assert!(WrathPolicy::Always.decide(ShimmerKind::Wrath, Some(true)).0);
+        assert!(!WrathPolicy::Never.decide(ShimmerKind::Wrath, Some(false)).0);
+        assert!(WrathPolicy::UnlessClot.decide(ShimmerKind::Wrath, Some(false)).0);
+        assert!(!WrathPolicy::UnlessClot.decide(ShimmerKind::Wrath, Some(true)).0);
+        assert_eq!(
+            WrathPolicy::UnlessClot.decide(ShimmerKind::Wrath, None),
+            (false, "can't tell whether Clot is active"),
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! The building rows of the store panel.
+//!
+//! Each row shows the building's name, its price under the name and, in large
+//! faded digits on the right, how many are owned. The game colours the price
+//! green when it's affordable and red when it isn't, so the price is read
+//! twice, once per colour, and whichever reading finds digits also tells us
+//! whether the row can be bought.
+//!
+//! Unscrolled, the rows sit where the `Layout` puts them. Once the panel has been scrolled
+//! they can be anywhere, so `scan_rows` looks for prices down the whole panel
+//! and works out which building each one belongs to from the price itself.
+
+use std::path::Path;
+use anyhow::{Context, Result};
+use crate::building::Building;
+use crate::layout::Layout;
+use crate::my_screenshot::{Region, Screenshot};
+use crate::notation::CookieAmount;
+use crate::ocr::{DigitReader, Reading};
+use super::found;
+
+const PRICE_GREEN: (u8, u8, u8) = (102, 255, 102);
+const PRICE_RED: (u8, u8, u8) = (255, 102, 102);
+const OWNED_GREY: (u8, u8, u8) = (150, 150, 150);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoreRow {
+    pub building: Building,
+    pub price: Option<CookieAmount>,
+    pub owned: Option<u32>,
+    pub affordable: bool,
+    /// A pixel of the price's first digit, whose colour tells whether it's affordable.
+    pub price_ink: Option<(i32, i32)>,
+    /// Where to click to buy one.
+    pub position: (i32, i32),
+}
+
+pub struct StoreDetector {
+    affordable_reader: DigitReader,
+    expensive_reader: DigitReader,
+    owned_reader: DigitReader,
+}
+
+impl StoreDetector {
+    /// `price_templates` are digits in the font of the price under the name,
+    /// `owned_templates` the big ones on the right of the row.
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(price_templates: P, owned_templates: Q) -> Result<Self> {
+        let mut affordable_reader = DigitReader::load(price_templates).context("store price templates")?;
+        affordable_reader.colour = PRICE_GREEN;
+        let mut expensive_reader = affordable_reader.clone();
+        expensive_reader.colour = PRICE_RED;
+        let mut owned_reader = DigitReader::load(owned_templates).context("owned count templates")?;
+        owned_reader.colour = OWNED_GREY;
+
+        Ok(Self { affordable_reader, expensive_reader, owned_reader })
+    }
+
+    /// Reads the rows visible in an unscrolled store, top to bottom.
+    pub fn read_rows(&self, screenshot: &Screenshot, layout: &Layout) -> Vec<StoreRow> {
+        Building::ALL[..layout.visible_rows].iter()
+            .map(|&building| {
+                let top = layout.row_top(building.store_order());
+                let price_region = Region::new(
+                    layout.text_left,
+                    top + layout.row_height / 2,
+                    layout.owned_left - layout.text_left,
+                    layout.row_height / 2,
+                );
+                let (price, affordable, price_ink) = match self.read_price(|reader| reader.read(screenshot, price_region)) {
+                    Some((reading, affordable)) => (reading.amount(), affordable, self.price_ink(screenshot, &reading, affordable)),
+                    None => (None, false, None),
+                };
+
+                StoreRow {
+                    building,
+                    price,
+                    owned: self.read_owned(screenshot, layout, top),
+                    affordable,
+                    price_ink,
+                    position: layout.row_position(top),
+                }
+            })
+            .collect()
+    }
+
+    /// Reads every row in the panel wherever it's scrolled to, top to bottom.
+    /// Rows whose price doesn't match any building are left out.
+    pub fn scan_rows(&self, screenshot: &Screenshot, layout: &Layout) -> Vec<StoreRow> {
+        let column = Region::new(layout.text_left, layout.store.y, layout.owned_left - layout.text_left, layout.store.height);
+        let mut rows = vec![];
+
+        let mut baseline = column.y;
+        while baseline < column.bottom() {
+            let price = self.read_price(|reader| reader.read_line(screenshot, column, baseline));
+            let (reading, affordable) = match price {
+                Some(price) => price,
+                None => {
+                    baseline += 1;
+                    continue;
+                }
+            };
+
+            // The first baseline that reads anything can be a row or two above the real
+            // one, catching only the flatter digits. Settle on whichever reads the most.
+            let (reading, affordable, found_at) = (baseline + 1..baseline + 4)
+                .filter_map(|lower| self.read_price(|reader| reader.read_line(screenshot, column, lower))
+                    .map(|(reading, affordable)| (reading, affordable, lower)))
+                .fold((reading, affordable, baseline), |best, next| {
+                    if next.0.glyphs.len() > best.0.glyphs.len() { next } else { best }
+                });
+
+            let top = found_at.saturating_sub(layout.row_height * 3 / 4);
+            let owned = self.read_owned(screenshot, layout, top);
+            let building = reading.amount()
+                .and_then(|price| Building::identify(price, owned.unwrap_or(0)));
+            if let Some(building) = building {
+                rows.push(StoreRow {
+                    building,
+                    price: reading.amount(),
+                    owned,
+                    affordable,
+                    price_ink: self.price_ink(screenshot, &reading, affordable),
+                    position: layout.row_position(top),
+                });
+            }
+            baseline = found_at + layout.row_height / 2;
+        }
+
+        rows
+    }
+
+    /// Runs `read` with the green and then the red price reader. Returns the first
+    /// reading with digits, and whether it was green.
+    fn read_price<F: Fn(&DigitReader) -> Reading>(&self, read: F) -> Option<(Reading, bool)> {
+        match found(read(&self.affordable_reader)) {
+            Some(reading) => Some((reading, true)),
+            None => found(read(&self.expensive_reader)).map(|reading| (reading, false)),
+        }
+    }
+
+    /// A pixel of `reading`'s first digit, in the colour it was read in.
+    fn price_ink(&self, screenshot: &Screenshot, reading: &Reading, affordable: bool) -> Option<(i32, i32)> {
+        let reader = if affordable { &self.affordable_reader } else { &self.expensive_reader };
+        let digit = reading.glyphs.iter().find(|glyph| glyph.ch.is_ascii_digit())?;
+        reader.ink(screenshot, digit).map(|(x, y)| (x as i32, y as i32))
+    }
+
+    /// The owned count of the row starting at `top`. The game leaves it out while
+    /// none are owned, which reads as `None`.
+    fn read_owned(&self, screenshot: &Screenshot, layout: &Layout, top: usize) -> Option<u32> {
+        let region = Region::new(layout.owned_left, top, layout.store.right() - layout.owned_left, layout.row_height);
+        found(self.owned_reader.read(screenshot, region))
+            .and_then(|reading| reading.value)
+            .map(|value| value as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use crate::detect::write_number;
+
+    const GREEN: Rgba<u8> = Rgba([PRICE_GREEN.0, PRICE_GREEN.1, PRICE_GREEN.2, 255]);
+    const RED: Rgba<u8> = Rgba([PRICE_RED.0, PRICE_RED.1, PRICE_RED.2, 255]);
+    const GREY: Rgba<u8> = Rgba([OWNED_GREY.0, OWNED_GREY.1, OWNED_GREY.2, 255]);
+
+    fn detector() -> StoreDetector {
+        StoreDetector::load("cookie_numbers", "cookie_numbers").unwrap()
+    }
+
+    fn blank() -> RgbaImage {
+        RgbaImage::from_pixel(1920, 1080, Rgba([60, 40, 30, 255]))
+    }
+
+    /// Writes a row starting at `top` the way the game lays it out.
+    fn row(frame: &mut RgbaImage, layout: &Layout, top: usize, price: &str, colour: Rgba<u8>, owned: Option<&str>) {
+        write_number(frame, price, layout.text_left as u32 + 4, top as u32 + 50, colour);
+        if let Some(owned) = owned {
+            write_number(frame, owned, layout.owned_left as u32 + 10, top as u32 + 45, GREY);
+        }
+    }
+
+    #[test]
+    fn reads_prices_in_green_and_red_and_the_owned_counts() {
+        let layout = Layout::default();
+        let mut frame = blank();
+        row(&mut frame, &layout, layout.row_top(0), "15", GREEN, None);
+        row(&mut frame, &layout, layout.row_top(1), "115", RED, Some("1"));
+        row(&mut frame, &layout, layout.row_top(2), "1,455", GREEN, Some("2"));
+        let frame = Screenshot::from(&frame);
+
+        let rows = detector().read_rows(&frame, &layout);
+        assert_eq!(rows.len(), layout.visible_rows);
+        let summary: Vec<_> = rows[..4].iter()
+            .map(|row| (row.building, row.price.map(CookieAmount::value), row.affordable, row.owned))
+            .collect();
+        assert_eq!(summary, vec![
+            (Building::Cursor, Some(15.0), true, None),
+            (Building::Grandma, Some(115.0), false, Some(1)),
+            (Building::Farm, Some(1_455.0), true, Some(2)),
+            (Building::Mine, None, false, None),
+        ]);
+
+        let (x, y) = rows[1].price_ink.unwrap();
+        assert_eq!(frame.pixel(x as usize, y as usize).unwrap().rgb(), PRICE_RED);
+        assert_eq!(rows[2].position, layout.row_position(layout.row_top(2)));
+    }
+
+    #[test]
+    fn tells_scrolled_rows_apart_by_price() {
+        let layout = Layout::default();
+        let mut frame = blank();
+        // Scrolled by a bit more than a third of a row, with a price that's no building's.
+        let top = layout.row_top(1) + 23;
+        row(&mut frame, &layout, top, "18,251", GREEN, Some("3"));
+        row(&mut frame, &layout, top + layout.row_height, "130,000", RED, None);
+        row(&mut frame, &layout, top + 2 * layout.row_height, "3", GREEN, None);
+        row(&mut frame, &layout, top + 3 * layout.row_height, "20,000,000", RED, None);
+        let frame = Screenshot::from(&frame);
+
+        let rows = detector().scan_rows(&frame, &layout);
+        let summary: Vec<_> = rows.iter()
+            .map(|row| (row.building, row.affordable, row.owned))
+            .collect();
+        assert_eq!(summary, vec![
+            (Building::Mine, true, Some(3)),
+            (Building::Factory, false, None),
+            (Building::Temple, false, None),
+        ]);
+        let (_, y) = rows[0].position;
+        assert!((y - layout.row_position(top).1).abs() <= 3, "{:?}", rows[0]);
+    }
+}
@@ -0,0 +1,253 @@
+//! Everything the bot does with the mouse goes through an `InputSink`, so the
+//! same detection code can drive the real cursor or just write down what it
+//! would have done.
+
+use std::fmt;
+use std::time::Instant;
+use enigo::{Enigo, MouseButton, MouseControllable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Move { x: i32, y: i32 },
+    /// Left click wherever the cursor was at the time.
+    Click { x: i32, y: i32 },
+    /// Positive is down, negative is up, in wheel notches.
+    Scroll { amount: i32 },
+    /// Something at (x, y) deliberately left unclicked.
+    Skip { x: i32, y: i32 },
+}
+
+/// Why the bot is about to use the mouse. Only dry runs look at it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reason {
+    pub what: String,
+    /// The colour that triggered it.
+    pub trigger: Option<Trigger>,
+}
+
+/// A colour the bot saw, and where on screen it saw it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trigger {
+    pub colour: (u8, u8, u8),
+    pub at: (i32, i32),
+}
+
+impl Reason {
+    pub fn new<S: Into<String>>(what: S) -> Self {
+        Self { what: what.into(), trigger: None }
+    }
+
+    pub fn with_colour(mut self, colour: (u8, u8, u8), at: (i32, i32)) -> Self {
+        self.trigger = Some(Trigger { colour, at });
+        self
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.what)?;
+        if let Some(Trigger { colour, at }) = self.trigger {
+            write!(f, ", saw {:?} at {:?}", colour, at)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait InputSink {
+    /// Says why the actions that follow are taken, until the next reason.
+    fn because(&mut self, _reason: Reason) {}
+    fn move_to(&mut self, x: i32, y: i32);
+    fn click(&mut self);
+    fn scroll(&mut self, amount: i32);
+    /// Says something at (x, y) is deliberately not clicked, and why.
+    fn skip(&mut self, x: i32, y: i32, reason: Reason) {
+        println!("not clicking at ({}, {}): {}", x, y, reason);
+    }
+}
+
+impl InputSink for Enigo {
+    fn move_to(&mut self, x: i32, y: i32) {
+        self.mouse_move_to(x, y);
+    }
+
+    fn click(&mut self) {
+        self.mouse_click(MouseButton::Left);
+    }
+
+    fn scroll(&mut self, amount: i32) {
+        self.mouse_scroll_y(amount);
+    }
+}
+
+/// Keeps a timestamped list of actions instead of touching the mouse.
+pub struct RecordingSink {
+    cursor: (i32, i32),
+    actions: Vec<(Instant, Action)>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self {
+            cursor: (0, 0),
+            actions: vec![],
+        }
+    }
+
+    /// Everything recorded so far, oldest first.
+    pub fn actions(&self) -> &[(Instant, Action)] {
+        &self.actions
+    }
+
+    /// Positions of all recorded clicks, oldest first.
+    pub fn clicks(&self) -> Vec<(i32, i32)> {
+        self.actions.iter()
+            .filter_map(|(_, action)| match *action {
+                Action::Click { x, y } => Some((x, y)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.actions.clear();
+    }
+}
+
+impl Default for RecordingSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputSink for RecordingSink {
+    fn move_to(&mut self, x: i32, y: i32) {
+        self.cursor = (x, y);
+        self.actions.push((Instant::now(), Action::Move { x, y }));
+    }
+
+    fn click(&mut self) {
+        let (x, y) = self.cursor;
+        self.actions.push((Instant::now(), Action::Click { x, y }));
+    }
+
+    fn scroll(&mut self, amount: i32) {
+        self.actions.push((Instant::now(), Action::Scroll { amount }));
+    }
+
+    fn skip(&mut self, x: i32, y: i32, _reason: Reason) {
+        self.actions.push((Instant::now(), Action::Skip { x, y }));
+    }
+}
+
+/// Prints every action and the reason for it instead of performing it, for dry runs.
+pub struct LoggingSink {
+    cursor: (i32, i32),
+    reason: Option<Reason>,
+}
+
+impl LoggingSink {
+    pub fn new() -> Self {
+        Self { cursor: (0, 0), reason: None }
+    }
+
+    fn log(&self, action: String) {
+        match &self.reason {
+            Some(reason) => println!("dry run: {}: {}", action, reason),
+            None => println!("dry run: {}: no reason given", action),
+        }
+    }
+}
+
+impl Default for LoggingSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputSink for LoggingSink {
+    fn because(&mut self, reason: Reason) {
+        self.reason = Some(reason);
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) {
+        // The cursor is put back on the cookie every time the mouse changes hands,
+        // mostly while it's already there. Those moves wouldn't do anything.
+        if self.cursor == (x, y) {
+            return;
+        }
+        self.cursor = (x, y);
+        self.log(format!("move to ({}, {})", x, y));
+    }
+
+    fn click(&mut self) {
+        self.log(format!("click at ({}, {})", self.cursor.0, self.cursor.1));
+        // A click finishes whatever the reason was for.
+        self.reason = None;
+    }
+
+    fn scroll(&mut self, amount: i32) {
+        self.log(format!("scroll {}", amount));
+    }
+
+    fn skip(&mut self, x: i32, y: i32, reason: Reason) {
+        println!("dry run: skip ({}, {}): {}", x, y, reason);
+    }
+}
+
+/// Moves every position by `origin` before passing it on. Layouts are measured
+/// from the corner of the monitor being captured, while the mouse moves over the
+/// whole desktop.
+pub struct OffsetSink<S> {
+    inner: S,
+    origin: (i32, i32),
+}
+
+impl<S: InputSink> OffsetSink<S> {
+    pub fn new(inner: S, origin: (i32, i32)) -> Self {
+        Self { inner, origin }
+    }
+}
+
+impl<S: InputSink> InputSink for OffsetSink<S> {
+    fn because(&mut self, reason: Reason) {
+        self.inner.because(reason);
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) {
+        self.inner.move_to(x + self.origin.0, y + self.origin.1);
+    }
+
+    fn click(&mut self) {
+        self.inner.click();
+    }
+
+    fn scroll(&mut self, amount: i32) {
+        self.inner.scroll(amount);
+    }
+
+    fn skip(&mut self, x: i32, y: i32, reason: Reason) {
+        self.inner.skip(x + self.origin.0, y + self.origin.1, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_by_the_monitor_origin() {
+        let mut sink = OffsetSink::new(RecordingSink::new(), (-1920, 200));
+        sink.move_to(287, 417);
+        sink.click();
+        sink.scroll(3);
+        sink.skip(10, 20, Reason::new("wrath cookie"));
+
+        let actions: Vec<Action> = sink.inner.actions().iter().map(|&(_, action)| action).collect();
+        assert_eq!(actions, vec![
+            Action::Move { x: -1633, y: 617 },
+            Action::Click { x: -1633, y: 617 },
+            Action::Scroll { amount: 3 },
+            Action::Skip { x: -1910, y: 220 },
+        ]);
+    }
+}
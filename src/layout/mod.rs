@@ -0,0 +1,370 @@
+//! Where things are on screen: the big cookie, the bank counter, the sugar lump,
+//! the buff bar, the upgrade tray and the store's building rows.
+//!
+//! `Layout::default()` is the hand-measured layout of a maximised 1920x1080
+//! browser window at 100% zoom, which is what the bot was written against.
+//! `Layout::calibrate` finds the same places on other windows from two anchors.
+//! The big cookie is always there; where it is places the left panel, and how
+//! big it is gives the zoom. The frame colour of an affordable upgrade places
+//! the upgrade tray, and with it the store, when there is one. The store is
+//! otherwise assumed to fill the right edge of the window, as it does at any
+//! size. The buff bar sits at the bottom of the left panel, so it keeps its 1080p
+//! distance to the bottom of the window. Everything else keeps its 1080p distance
+//! to the nearest anchor, scaled.
+
+pub mod profile;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use crate::building::Building;
+use crate::config::UpgradeConfig;
+use crate::my_screenshot::Region;
+use crate::ocr::PixelSource;
+
+/// Big cookie diameter at 100% zoom.
+const COOKIE_DIAMETER: f64 = 256.0;
+/// Store panel width at 100% zoom, scrollbar included.
+const STORE_WIDTH: f64 = 320.0;
+/// Cookie coloured pixels below which there's taken to be no cookie on screen.
+const MIN_COOKIE_PIXELS: usize = 200;
+/// How far apart pixels are sampled while looking for the big cookie.
+const COOKIE_STEP: usize = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    /// Centre of the big cookie, where the auto clicker clicks.
+    pub big_cookie: (i32, i32),
+    /// Band of the left panel holding the bank counter.
+    pub bank: Region,
+    /// Corner of the left panel the sugar lump grows in.
+    pub lump: Region,
+    /// Band along the bottom of the left panel where buff icons show up.
+    pub buffs: Region,
+    /// One pixel per upgrade slot in the tray, left to right, that has the
+    /// upgrade frame colour while the slot holds an affordable upgrade.
+    pub upgrade_slots: Vec<(i32, i32)>,
+    /// The part of the store panel rows can scroll through, upgrade tray included.
+    pub store: Region,
+    /// Top of the first building row in an unscrolled store.
+    pub first_row_top: usize,
+    pub row_height: usize,
+    /// Rows visible without scrolling, starting from Cursor.
+    pub visible_rows: usize,
+    /// Where a row's name and price start, past the building's icon.
+    pub text_left: usize,
+    /// Where a row's owned count starts.
+    pub owned_left: usize,
+    /// Pixel on each visible row's name that's white while the row is affordable.
+    pub building_probes: Vec<(Building, i32, i32)>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        // Measured by hand on a 1080p screen. The y values used to be 3px lower,
+        // because the old `get_pixel` read three rows above the one it was given;
+        // these are the rows that were actually being sampled.
+        Self {
+            big_cookie: (287, 417),
+            bank: Region::new(0, 57, 575, 80),
+            lump: Region::new(0, 30, 100, 100),
+            buffs: Region::new(0, 1000, 575, 80),
+            upgrade_slots: (0..5).map(|slot| (1605 + slot * 60, 82)).collect(),
+            store: Region::new(1600, 57, 320, 1023),
+            first_row_top: 180,
+            row_height: 64,
+            visible_rows: 10,
+            text_left: 1664,
+            owned_left: 1830,
+            building_probes: vec![
+                (Building::Cursor, 1692, 212),
+                (Building::Grandma, 1723, 276),
+                (Building::Farm, 1677, 341),
+                (Building::Mine, 1694, 395),
+                (Building::Factory, 1677, 467),
+                (Building::Bank, 1677, 531),
+                (Building::Temple, 1681, 592),
+                (Building::WizardTower, 1685, 656),
+                (Building::Shipment, 1691, 722),
+                (Building::AlchemyLab, 1692, 783),
+            ],
+        }
+    }
+}
+
+impl Layout {
+    /// Finds the layout of the game in `source`, recognising upgrades by the colour
+    /// in `upgrades`. Fails if the big cookie isn't on screen.
+    pub fn calibrate<S: PixelSource>(source: &S, upgrades: &UpgradeConfig) -> Result<Layout> {
+        let (cookie, diameter) = match find_big_cookie(source) {
+            Some(found) => found,
+            None => bail!("couldn't find the big cookie"),
+        };
+        let scale = diameter as f64 / COOKIE_DIAMETER;
+        let default = Layout::default();
+
+        // Without an upgrade to go by, the tray keeps its 1080p height relative to the cookie.
+        let (first_slot, slot_top) = default.upgrade_slots[0];
+        let expected_left = source.width() as f64 - STORE_WIDTH * scale;
+        let (store_left, tray_top) = match find_upgrade_tray(source, upgrades, scale) {
+            Some((x, y)) => {
+                // The leftmost affordable upgrade needn't be in the first slot. Step back
+                // whole slots to whichever start lines the store up with the right edge.
+                let spacing = (default.upgrade_slots[1].0 - first_slot) as f64 * scale;
+                let from_tray = x as f64 - (first_slot - default.store.x as i32) as f64 * scale;
+                let slots_back = ((from_tray - expected_left) / spacing).round().max(0.0);
+                (from_tray - slots_back * spacing, y as f64)
+            }
+            None => (expected_left, cookie.1 as f64 + (slot_top - default.big_cookie.1) as f64 * scale),
+        };
+        Ok(Layout::anchored((source.width(), source.height()), cookie, (store_left, tray_top), scale))
+    }
+
+    /// The 1080p layout scaled and moved onto a `screen` sized window, with the big
+    /// cookie at `cookie` and the store's left edge and upgrade tray top at `store`.
+    pub fn anchored(screen: (usize, usize), cookie: (i32, i32), store: (f64, f64), scale: f64) -> Layout {
+        let default = Layout::default();
+        let left_panel = Anchor {
+            from: (default.big_cookie.0 as f64, default.big_cookie.1 as f64),
+            to: (cookie.0 as f64, cookie.1 as f64),
+            scale,
+        };
+        let store = Anchor {
+            from: (default.store.x as f64, default.upgrade_slots[0].1 as f64),
+            to: store,
+            scale,
+        };
+
+        let mut store_region = store.region(default.store);
+        store_region.width = store_region.width.min(screen.0.saturating_sub(store_region.x));
+        store_region.height = screen.1.saturating_sub(store_region.y);
+
+        let mut buffs = left_panel.region(default.buffs);
+        let from_bottom = (1080 - default.buffs.y) as f64 * scale;
+        buffs.y = (screen.1 as f64 - from_bottom).round().max(0.0) as usize;
+        buffs.height = buffs.height.min(screen.1.saturating_sub(buffs.y));
+
+        let first_row_top = store.point(0, default.first_row_top as i32).1.max(0) as usize;
+        let row_height = store.length(default.row_height);
+        // Only the first ten rows have probes, so there's no use counting further.
+        let visible_rows = ((store_region.bottom().saturating_sub(first_row_top)) / row_height.max(1))
+            .min(default.visible_rows);
+
+        Layout {
+            big_cookie: cookie,
+            bank: left_panel.region(default.bank),
+            lump: left_panel.region(default.lump),
+            buffs,
+            upgrade_slots: default.upgrade_slots.iter().map(|&(x, y)| store.point(x, y)).collect(),
+            store: store_region,
+            first_row_top,
+            row_height,
+            visible_rows,
+            text_left: store.point(default.text_left as i32, 0).0.max(0) as usize,
+            owned_left: store.point(default.owned_left as i32, 0).0.max(0) as usize,
+            building_probes: default.building_probes.iter()
+                .take(visible_rows)
+                .map(|&(building, x, y)| {
+                    let (x, y) = store.point(x, y);
+                    (building, x, y)
+                })
+                .collect(),
+        }
+    }
+
+    /// Zoom relative to the 1080p layout, going by the store's row height.
+    pub fn scale(&self) -> f64 {
+        self.row_height as f64 / Layout::default().row_height as f64
+    }
+
+    /// Top of the row `store_order` places down in an unscrolled store.
+    pub fn row_top(&self, store_order: usize) -> usize {
+        self.first_row_top + store_order * self.row_height
+    }
+
+    /// Top of the row `store_order` places down in a store scrolled to the bottom, where
+    /// the last building's row ends at the bottom of the panel. That's only so with every
+    /// building unlocked; `None` for rows scrolled off the top.
+    pub fn scrolled_row_top(&self, store_order: usize) -> Option<usize> {
+        let from_bottom = (Building::ALL.len() - store_order) * self.row_height;
+        self.store.bottom().checked_sub(from_bottom).filter(|&top| top >= self.store.y)
+    }
+
+    /// The part of the row starting at `top` that has the building's name in it.
+    pub fn name_band(&self, top: usize) -> Region {
+        Region::new(self.text_left, top + self.row_height * 5 / 16, self.owned_left - self.text_left, self.row_height / 4)
+    }
+
+    /// Where to click to buy from the row starting at `top`.
+    pub fn row_position(&self, top: usize) -> (i32, i32) {
+        ((self.store.x + self.store.right()) as i32 / 2, (top + self.row_height / 2) as i32)
+    }
+
+    /// Middle of the store panel, for scrolling it.
+    pub fn store_centre(&self) -> (i32, i32) {
+        ((self.store.x + self.store.width / 2) as i32, (self.store.y + self.store.height / 2) as i32)
+    }
+}
+
+/// Maps 1080p coordinates near `from` to the same place relative to `to`.
+struct Anchor {
+    from: (f64, f64),
+    to: (f64, f64),
+    scale: f64,
+}
+
+impl Anchor {
+    fn point(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            (self.to.0 + (x as f64 - self.from.0) * self.scale).round() as i32,
+            (self.to.1 + (y as f64 - self.from.1) * self.scale).round() as i32,
+        )
+    }
+
+    fn length(&self, length: usize) -> usize {
+        (length as f64 * self.scale).round() as usize
+    }
+
+    fn region(&self, region: Region) -> Region {
+        let (x, y) = self.point(region.x as i32, region.y as i32);
+        Region::new(x.max(0) as usize, y.max(0) as usize, self.length(region.width), self.length(region.height))
+    }
+}
+
+/// Dough and chocolate chips: reds through browns, but not the pale yellow of the
+/// shine behind the cookie or the blue of the background.
+fn is_cookie((r, g, b): (u8, u8, u8)) -> bool {
+    let (r, g, b) = (r as u32, g as u32, b as u32);
+    r >= 60 && g * 100 >= r * 40 && g * 100 <= r * 85 && b * 100 <= g * 80
+}
+
+/// Centre and diameter of the big cookie, looked for in the left two fifths of the screen.
+fn find_big_cookie<S: PixelSource>(source: &S) -> Option<((i32, i32), usize)> {
+    let (mut xs, mut ys) = (vec![], vec![]);
+    for y in (0..source.height()).step_by(COOKIE_STEP) {
+        for x in (0..source.width() * 2 / 5).step_by(COOKIE_STEP) {
+            if is_cookie(source.rgb(x, y)) {
+                xs.push(x);
+                ys.push(y);
+            }
+        }
+    }
+    if xs.len() < MIN_COOKIE_PIXELS {
+        return None;
+    }
+
+    // Medians rather than means, so stray brown elsewhere in the panel doesn't drag the centre.
+    xs.sort_unstable();
+    ys.sort_unstable();
+    let (x, y) = (xs[xs.len() / 2], ys[ys.len() / 2]);
+
+    // Chips and the shading between them can break the run, so allow short gaps.
+    let gap = 8;
+    let across = chord(x, gap, |x| x < source.width() && is_cookie(source.rgb(x, y)));
+    let down = chord(y, gap, |y| y < source.height() && is_cookie(source.rgb(x, y)));
+    let diameter = across.max(down);
+
+    Some(((x as i32, y as i32), diameter))
+}
+
+/// Length of the run of positions around `centre` that pass `inside`, bridging
+/// gaps of up to `gap` positions.
+fn chord<F: Fn(usize) -> bool>(centre: usize, gap: usize, inside: F) -> usize {
+    let reach = |step: isize| {
+        let (mut last, mut at, mut missed) = (centre, centre as isize, 0);
+        while missed <= gap {
+            at += step;
+            if at < 0 {
+                break;
+            }
+            if inside(at as usize) {
+                last = at as usize;
+                missed = 0;
+            } else {
+                missed += 1;
+            }
+        }
+        last
+    };
+    reach(1) - reach(-1) + 1
+}
+
+/// The first pixel of upgrade frame colour along the top of the tray, which is the
+/// leftmost affordable upgrade's slot pixel. Looked for in the top quarter of the
+/// right half of the screen.
+fn find_upgrade_tray<S: PixelSource>(source: &S, upgrades: &UpgradeConfig, scale: f64) -> Option<(usize, usize)> {
+    let (colour, tolerance) = (upgrades.colour, upgrades.tolerance);
+    let matches = |(r, g, b): (u8, u8, u8)| {
+        r.abs_diff(colour.0) <= tolerance && g.abs_diff(colour.1) <= tolerance && b.abs_diff(colour.2) <= tolerance
+    };
+    let top = (0..source.height() / 4)
+        .find(|&y| (source.width() / 2..source.width()).any(|x| matches(source.rgb(x, y))))?;
+
+    // A frame is a few pixels wide at most; anything further down is inside the upgrade.
+    let depth = (4.0 * scale).ceil() as usize;
+    let left = (source.width() / 2..source.width())
+        .find(|&x| (top..top + depth).any(|y| y < source.height() && matches(source.rgb(x, y))))?;
+
+    Some((left, top))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    const BACKGROUND: Rgba<u8> = Rgba([20, 40, 90, 255]);
+    const DOUGH: Rgba<u8> = Rgba([200, 130, 60, 255]);
+
+    /// A `width`x`height` screen with a plain cookie of `radius` centred on `cookie`.
+    fn screen(width: u32, height: u32, cookie: (i32, i32), radius: i32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (dx, dy) = (x as i32 - cookie.0, y as i32 - cookie.1);
+            if dx * dx + dy * dy <= radius * radius { DOUGH } else { BACKGROUND }
+        })
+    }
+
+    fn near(a: i32, b: i32, slack: i32) -> bool {
+        (a - b).abs() <= slack
+    }
+
+    #[test]
+    fn calibrates_a_1080p_screen_to_the_default_layout() {
+        let image = screen(1920, 1080, (287, 417), 128);
+        let layout = Layout::calibrate(&image, &UpgradeConfig::default()).unwrap();
+        let default = Layout::default();
+        assert!(near(layout.big_cookie.0, 287, 1) && near(layout.big_cookie.1, 417, 1), "{:?}", layout.big_cookie);
+        assert_eq!(layout.row_height, default.row_height);
+        assert!(near(layout.store.x as i32, default.store.x as i32, 2), "{:?}", layout.store);
+        assert_eq!(layout.visible_rows, default.visible_rows);
+        for (&(x, y), &(dx, dy)) in layout.upgrade_slots.iter().zip(&default.upgrade_slots) {
+            assert!(near(x, dx, 2) && near(y, dy, 2), "slot at ({}, {}) instead of ({}, {})", x, y, dx, dy);
+        }
+    }
+
+    #[test]
+    fn lines_the_store_up_with_an_affordable_upgrade() {
+        let upgrades = UpgradeConfig::default();
+        let (r, g, b) = upgrades.colour;
+        // A 960x540 window, with something affordable in the second upgrade slot.
+        let mut image = screen(960, 540, (143, 208), 64);
+        for x in 832..850 {
+            for y in 41..44 {
+                image.put_pixel(x, y, Rgba([r, g, b, 255]));
+            }
+        }
+        let layout = Layout::calibrate(&image, &upgrades).unwrap();
+        assert!((layout.scale() - 0.5).abs() < 0.02, "scale {}", layout.scale());
+        assert!(near(layout.store.x as i32, 800, 2), "{:?}", layout.store);
+        assert!(near(layout.upgrade_slots[1].0, 832, 2) && near(layout.upgrade_slots[1].1, 41, 1),
+                "{:?}", layout.upgrade_slots);
+        assert!(layout.store.right() <= 960 && layout.store.bottom() <= 540);
+    }
+
+    #[test]
+    fn fails_without_a_cookie() {
+        let image = RgbaImage::from_pixel(1920, 1080, BACKGROUND);
+        let err = Layout::calibrate(&image, &UpgradeConfig::default()).unwrap_err();
+        assert_eq!(err.to_string(), "couldn't find the big cookie");
+    }
+}
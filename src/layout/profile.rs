@@ -0,0 +1,51 @@
+//! Layouts measured by hand with the calibration wizard, saved under a name so
+//! each machine can keep its own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use super::Layout;
+
+/// Where profiles are saved and looked for.
+pub const PROFILE_DIR: &str = "profiles/";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// Width and height of the screen it was measured on.
+    pub resolution: (usize, usize),
+    pub layout: Layout,
+    /// What was pointed at for each target, in the order they were asked for.
+    pub samples: Vec<Sample>,
+}
+
+/// One target the user pointed at.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub target: String,
+    pub position: (i32, i32),
+    /// Colour under the cursor at the time.
+    pub colour: (u8, u8, u8),
+}
+
+impl Profile {
+    pub fn path(name: &str) -> PathBuf {
+        Path::new(PROFILE_DIR).join(format!("{}.toml", name))
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let path = Self::path(name);
+        let text = fs::read_to_string(&path).with_context(|| format!("reading profile {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing profile {}", path.display()))
+    }
+
+    /// Writes the profile to `PROFILE_DIR`, replacing any of the same name.
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = Self::path(&self.name);
+        fs::create_dir_all(PROFILE_DIR).with_context(|| format!("creating {}", PROFILE_DIR))?;
+        let text = toml::to_string(self).context("serialising the profile")?;
+        fs::write(&path, text).with_context(|| format!("writing profile {}", path.display()))?;
+        Ok(path)
+    }
+}
@@ -1,256 +1,622 @@
-#![feature(core_intrinsics)]
-
 extern crate core;
 extern crate image;
 
 pub mod my_screenshot;
+pub mod input;
+pub mod controller;
+pub mod ocr;
+pub mod notation;
+pub mod detect;
+pub mod building;
+pub mod layout;
+pub mod config;
+pub mod wizard;
+pub mod cli;
+pub mod combo;
 
-use inputbot::{KeybdKey::*, MouseButton::*, *};
+use inputbot::{KeybdKey::*, *};
 use std::{thread::sleep, time::Duration};
-use std::backtrace::BacktraceStatus::Captured;
-use std::borrow::Borrow;
-use std::io::ErrorKind::WouldBlock;
-use std::path::Path;
-use enigo::{Enigo, MouseControllable, MouseButton};
+use std::path::PathBuf;
+use enigo::Enigo;
 use std::thread;
-use std::time::{Instant, SystemTime};
-use anyhow::Result;
+use std::sync::Arc;
+use std::time::Instant;
+use anyhow::{Context, Result};
 use chrono::Utc;
-use image::{DynamicImage, GenericImageView};
-use crate::my_screenshot::{Screenshot};
+use clap::Parser;
+use crate::cli::{Cli, Command, Common};
+use image::RgbaImage;
+use crate::my_screenshot::{monitors, CaptureError, CaptureSource, FrameInfo, Region, Screenshot};
 use crate::my_screenshot::ffi::Capturer;
-use image::io::Reader as ImageReader;
+use crate::my_screenshot::replay::{Pacing, ReplaySource};
+use crate::input::{InputSink, LoggingSink, OffsetSink, Reason};
+use crate::ocr::DigitReader;
+use crate::controller::{Controller, MouseGuard};
+use crate::combo::ComboEngine;
+use crate::detect::Detector;
+use crate::detect::buffs::Buff;
+use crate::detect::lump::{Harvester, Lump};
+use crate::detect::shimmer::ShimmerDetector;
+use crate::detect::store::{StoreDetector, StoreRow};
+use crate::building::Building;
+use crate::layout::Layout;
+use crate::config::{BuildingConfig, ClickerConfig, Config, GoldenConfig, UpgradeConfig};
 
-static mut RUNNING: bool = true;
-static mut CURSOR_ON_COOKIE: bool = false;
+/// Live capture of `screen`, or frames replayed from `replay`.
+fn open_capture_source(screen: usize, replay: &Option<(PathBuf, Pacing)>) -> Result<Box<dyn CaptureSource>, CaptureError> {
+    Ok(match replay {
+        Some((path, pacing)) => Box::new(ReplaySource::open(path, *pacing)?),
+        None => Box::new(Capturer::new(screen)),
+    })
+}
 
-fn find_all(path: &'static str) -> Vec<(i32, i32)> {
-    let line_y = 31;
+/// Where `screen`'s top left corner is on the desktop.
+fn monitor_origin(screen: usize) -> Result<(i32, i32)> {
+    match monitors()?.get(screen) {
+        Some(monitor) => Ok(monitor.origin()),
+        None => Err(CaptureError::NoSuchScreen(screen).into()),
+    }
+}
 
-    let holder = ImageReader::open("test_holder.png").unwrap().decode().unwrap();
+/// First wait after a failed capture. It doubles with every failure in a row.
+const CAPTURE_BACKOFF: Duration = Duration::from_millis(250);
+/// Longest wait between two capture attempts.
+const MAX_CAPTURE_BACKOFF: Duration = Duration::from_secs(60);
+/// Failed captures in a row after which the capturer is set up again from scratch.
+const REINIT_AFTER: u32 = 3;
 
-    /*for x in 0..to_find.width() {
-        if to_find.get_pixel(x, to_find.height() - 1).0 == [0, 0, 0, 255] {
-            let anchor: (i32, i32) = (x as i32, (to_find.height() - 1) as i32);
-            for x_holder in 0..holder.width() {
-                let pixel_color = holder.get_pixel(x_holder, line_height).0;
-                if pixel_color[0] == 255 && pixel_color[1] == 255 && pixel_color[2] == 255 {
+/// A screenshot from `capturer`, waiting out failures that may clear up on their
+/// own, like a locked screen or a display mode switch. Only returns the errors
+/// that won't.
+fn capture(capturer: &mut dyn CaptureSource) -> Result<Screenshot, CaptureError> {
+    capture_waiting(capturer, sleep)
+}
 
+/// `capture`, with `wait` doing the waiting between attempts.
+fn capture_waiting<W: FnMut(Duration)>(capturer: &mut dyn CaptureSource, mut wait: W) -> Result<Screenshot, CaptureError> {
+    let mut failures = 0;
+    let mut backoff = CAPTURE_BACKOFF;
+    loop {
+        let err = match capturer.get_screenshot() {
+            Ok(screenshot) => {
+                if failures > 0 {
+                    println!("capturing again after {} failed attempts", failures);
                 }
+                return Ok(screenshot);
             }
+            Err(err) if err.is_retryable() => err,
+            Err(err) => return Err(err),
+        };
 
-            is_first_black = false;
-        }
-    }*/
-
-    let mut numbers: Vec<DynamicImage> = vec![];
-
-    for i in 0..10 {
-        numbers.push(
-            ImageReader::open(path.to_owned() + format!("{}.png", i).as_str())
-            .unwrap()
-            .decode()
-            .unwrap()
-        );
-    }
-
-    'line: for x in 0..holder.width() {
-        if holder.get_pixel(x, line_y).0 == [255, 255, 255, 255] {
-            let anchor = (x as i32, line_y as i32);
-            'number: for number in 0..10 {
-                let to_find = &numbers[number];
-                let mut matches = true;
-                for y in (0..to_find.height()).rev() {
-                    for x in 0..to_find.width() {
-                        if to_find.get_pixel(x, y).0 == [0, 0, 0, 255] {
-                            let (x_holder_check, y_holder_check) = (anchor.0 as u32 + x, anchor.1 as u32 - (to_find.height() as u32 - y as u32 - 1u32));
-                            if holder.get_pixel(x_holder_check as u32, y_holder_check as u32).0 != [255, 255, 255, 255] {
-                                matches = false;
-                                continue 'number;
-                            }
-                        }
-                    }
-                }
-                println!("{} {} {:?}", matches, number, anchor);
-                continue 'line;
-            }
-            //println!("{}, {:?}", matches, anchor);
+        failures += 1;
+        let reinit = err.needs_reinit() || failures % REINIT_AFTER == 0;
+        println!("capture failed: {}, {} in {:?}", err, if reinit { "starting over" } else { "retrying" }, backoff);
+        if reinit {
+            capturer.reset();
         }
+        wait(backoff);
+        backoff = (backoff * 2).min(MAX_CAPTURE_BACKOFF);
     }
+}
 
+/// One frame, from `png` if given and from `screen` otherwise.
+fn grab_frame(screen: usize, png: &Option<PathBuf>) -> Result<Screenshot> {
+    let mut source: Box<dyn CaptureSource> = match png {
+        Some(path) => Box::new(ReplaySource::file(path)),
+        None => Box::new(Capturer::new(screen)),
+    };
+    Ok(source.get_screenshot()?)
+}
 
-    vec![]
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let common = cli.common;
+    let config = Config::load(&common.config)?;
+
+    match cli.command.unwrap_or(Command::Run { replay: None, fast: false }) {
+        Command::Run { replay, fast } => {
+            let pacing = if fast { Pacing::AsFastAsPossible } else { Pacing::RealTime };
+            run(&common, &config, replay.map(|path| (path, pacing)))
+        }
+        Command::Calibrate { name } => {
+            let origin = monitor_origin(common.screen)?;
+            let profile = wizard::run(&name, &mut Capturer::new(common.screen), origin)?;
+            if common.dry_run {
+                println!("{:#?}", profile.layout);
+            } else {
+                println!("saved {}", profile.save()?.display());
+            }
+            Ok(())
+        }
+        Command::Monitors => {
+            for (screen, monitor) in monitors()?.iter().enumerate() {
+                println!("{}: {}x{} at ({}, {}){}", screen, monitor.width, monitor.height, monitor.x, monitor.y,
+                         if monitor.primary { ", primary" } else { "" });
+            }
+            Ok(())
+        }
+        Command::Capture { output } => {
+            let screenshot = grab_frame(common.screen, &None)?;
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("frame-{}.png", Utc::now().timestamp_millis())));
+            if common.dry_run {
+                println!("captured {}x{}, not writing {}", screenshot.width(), screenshot.height(), output.display());
+            } else {
+                screenshot.save_png(&output, &FrameInfo::now(Some(common.screen)))?;
+                println!("saved {}", output.display());
+            }
+            Ok(())
+        }
+        Command::Ocr { png, region, templates, colour, tolerance } => {
+            let image = match &png {
+                Some(path) => image::open(path)?.to_rgba8(),
+                None => RgbaImage::from(&grab_frame(common.screen, &None)?),
+            };
+            let mut reader = DigitReader::load(templates.as_ref().unwrap_or(&config.templates.bank))?;
+            if let Some(colour) = colour {
+                reader.colour = (colour[0], colour[1], colour[2]);
+            }
+            if let Some(tolerance) = tolerance {
+                reader.tolerance = tolerance;
+            }
+            let region = region
+                .map(|values| Region::new(values[0], values[1], values[2], values[3]))
+                .unwrap_or(Region::new(0, 0, image.width() as usize, image.height() as usize));
+            let reading = reader.read(&image, region);
+            println!("{:?} -> {:?}", reading.text, reading.value);
+            for glyph in &reading.glyphs {
+                println!("    '{}' at ({}, {}), {} wide", glyph.ch, glyph.x, glyph.y, glyph.width);
+            }
+            Ok(())
+        }
+        Command::Inspect { x, y, png } => {
+            let screenshot = grab_frame(common.screen, &png)?;
+            match check_pixel_color(&screenshot, x, y) {
+                Some(colour) => println!("({}, {}) is {:?}", x, y, colour),
+                None => println!("({}, {}) is off the {}x{} frame", x, y, screenshot.width(), screenshot.height()),
+            }
+            Ok(())
+        }
+    }
 }
 
-fn main() {
-    //find_all("cookie_numbers/");
-    let mut capturer = Capturer::new(0);
-    let mut enigo = Enigo::new();
+fn run(common: &Common, config: &Config, replay: Option<(PathBuf, Pacing)>) -> Result<()> {
+    // Replayed frames weren't taken from any monitor in particular.
+    let input: Box<dyn InputSink + Send> = if common.dry_run {
+        Box::new(LoggingSink::new())
+    } else if replay.is_some() {
+        Box::new(Enigo::new())
+    } else {
+        let origin = monitor_origin(common.screen)?;
+        println!("playing on monitor {}, at {:?} on the desktop", common.screen, origin);
+        Box::new(OffsetSink::new(Enigo::new(), origin))
+    };
+    let mut capturer = open_capture_source(common.screen, &replay)?;
+    let detector = Arc::new(Detector::load(&config.templates, &config.buffs));
+    let layout = calibrate(&mut *capturer, &detector, config);
+    let controller = Controller::new(input, layout);
 
-    bind_keybinds();
+    bind_keybinds(&controller);
     thread::spawn(|| {
         handle_input_events();
     });
 
-    enigo.mouse_move_to(287, 417);
-    unsafe { CURSOR_ON_COOKIE = true; }
-    thread::spawn(|| {
-        auto_click();
-    });
+    // A dry run only watches. Logging a thousand cookie clicks a second would
+    // bury everything else.
+    if common.dry_run {
+        println!("dry run: not clicking the big cookie");
+    } else {
+        let click_controller = controller.clone();
+        let clicker_config = config.clicker.clone();
+        let combo = ComboEngine::new(config.combo.clone());
+        thread::spawn(move || {
+            auto_click(&click_controller, &clicker_config, combo);
+        });
+    }
 
-    thread::spawn(|| {
-        let mut enigo = Enigo::new();
-        let mut building_capturer = Capturer::new(0);
+    if config.golden.enabled {
+        let golden_replay = replay.clone();
+        let golden_controller = controller.clone();
+        let golden_detector = detector.clone();
+        let golden_config = config.golden.clone();
+        let screen = common.screen;
+        thread::spawn(move || {
+            match open_capture_source(screen, &golden_replay) {
+                Ok(mut capturer) => click_golden_cookies(&golden_controller, &golden_detector.shimmers, &mut *capturer, &golden_config),
+                Err(err) => println!("not looking for golden cookies: {}", err),
+            }
+        });
+    }
+
+    let screen = common.screen;
+    let building_replay = replay.clone();
+    let building_controller = controller.clone();
+    let building_detector = detector.clone();
+    let building_config = config.buildings.clone();
+    thread::spawn(move || {
+        let mut building_capturer = match open_capture_source(screen, &building_replay) {
+            Ok(capturer) => capturer,
+            Err(err) => {
+                println!("not buying buildings: {}", err);
+                return;
+            }
+        };
         loop {
-            let screenshot = building_capturer.get_screenshot().unwrap();
-            check_buildings(&screenshot, &mut enigo, 0);
-            sleep(Duration::from_secs(10 * 60));
+            if building_controller.is_running() {
+                let screenshot = match capture(&mut *building_capturer) {
+                    Ok(screenshot) => screenshot,
+                    Err(err) => {
+                        println!("stopped buying buildings: {}", err);
+                        return;
+                    }
+                };
+                let layout = building_controller.layout();
+                let rows = building_detector.store.as_ref().map(|store| store.read_rows(&screenshot, &layout));
+                let mut mouse = building_controller.take_mouse();
+                check_buildings(&screenshot, rows.as_deref(), &layout, &building_config, &mut *mouse, 0);
+                mouse = check_buildings_below_fold(&building_controller, mouse, &mut *building_capturer,
+                                                   building_detector.store.as_ref(), &layout, &building_config);
+                drop(mouse);
+                if let Some(cps) = building_controller.state().cps {
+                    println!("production: {} per second", cps);
+                }
+            }
+            sleep(building_config.interval());
         }
     });
 
+    let mut harvester = Harvester::new(config.lumps.harvest);
     loop {
-        unsafe { if RUNNING == true {
-            let screenshot = capturer.get_screenshot().unwrap();
-            if !RUNNING { continue; }
-            check_upgrades(&screenshot, &mut enigo);
+        if controller.is_running() {
+            let screenshot = match capture(&mut *capturer) {
+                Ok(screenshot) => screenshot,
+                Err(CaptureError::ReplayEnded) => {
+                    println!("replay finished");
+                    return Ok(());
+                }
+                Err(err) => return Err(err).context("capturing the screen"),
+            };
+            if capturer.display_changed() {
+                recalibrate(&controller, &detector, config, &screenshot);
+            }
+            let layout = controller.layout();
+            let state = detector.observe(&screenshot, &layout);
+            let lump = state.lump.clone();
+            controller.publish(state);
+            let mut mouse = controller.take_mouse();
+            if !controller.is_running() { continue; }
+            if let Some(lump) = &lump {
+                harvest_lump(lump, &mut harvester, &mut *mouse, !common.dry_run);
+            }
+            check_upgrades(&screenshot, &layout, &config.upgrades, &mut *mouse);
         } else {
             sleep(Duration::from_millis(100));
-        }}
+        }
     }
 }
 
-fn auto_click() {
-    let mut enigo = Enigo::new();
+/// Calibrates the layout off one frame, unless the config fixes it. Falls back to
+/// the configured layout when calibration fails, which unless changed is the
+/// 1920x1080 one the bot was written for.
+fn calibrate(capturer: &mut dyn CaptureSource, detector: &Detector, config: &Config) -> Layout {
+    if config.fixed_layout || config.profile.is_some() {
+        return config.layout.clone();
+    }
+    let screenshot = match capturer.get_screenshot() {
+        Ok(screenshot) => screenshot,
+        Err(err) => {
+            println!("couldn't take a screenshot to calibrate from, using the configured layout: {}", err);
+            return config.layout.clone();
+        }
+    };
+    match detector.calibrate(&screenshot, &config.upgrades) {
+        Ok(layout) => {
+            println!("calibrated: {:?}", layout);
+            layout
+        }
+        Err(err) => {
+            println!("calibration failed, using the configured layout: {:#}", err);
+            config.layout.clone()
+        }
+    }
+}
+
+/// Calibrates again off `screenshot` after the display changed under the bot.
+/// Keeps the old layout if that fails, or if the config fixes it.
+fn recalibrate(controller: &Controller, detector: &Detector, config: &Config, screenshot: &Screenshot) {
+    if config.fixed_layout || config.profile.is_some() {
+        println!("the display changed, but the layout is fixed by the config and may be off now");
+        return;
+    }
+    match detector.calibrate(screenshot, &config.upgrades) {
+        Ok(layout) => {
+            println!("display changed, recalibrated: {:?}", layout);
+            controller.set_layout(layout);
+        }
+        Err(err) => println!("display changed, but recalibration failed, keeping the old layout: {:#}", err),
+    }
+}
+
+/// How often the auto clicker looks at the buffs to pick its pace.
+const COMBO_CHECK: Duration = Duration::from_millis(100);
+
+/// Clicks the big cookie at whatever pace `combo` sets from the published buffs.
+fn auto_click(controller: &Controller, config: &ClickerConfig, mut combo: ComboEngine) {
     let mut click_counter: u128 = 0;
     let mut send_message = false;
+    let mut checked: Option<Instant> = None;
     loop {
-        unsafe {
-            if CURSOR_ON_COOKIE && RUNNING {
-                enigo.mouse_click(MouseButton::Left);
-                if click_counter + 100 > u128::MAX && !send_message {
-                    println!("reached max value at {}", Utc::now());
-                    send_message = true;
-                } else {
-                    click_counter += 1;
+        if controller.is_running() {
+            if checked.is_none_or(|checked| checked.elapsed() >= COMBO_CHECK) {
+                let step = combo.update(controller.state().buffs.as_deref());
+                if let Some((x, y)) = step.cast {
+                    let mut mouse = controller.take_mouse();
+                    mouse.because(Reason::new("a combo just started, casting Force the Hand of Fate"));
+                    mouse.move_to(x, y);
+                    mouse.click();
+                    println!("cast Force the Hand of Fate");
                 }
-                sleep(Duration::from_micros(1000));
-            } else if !RUNNING {
-                println!("click count: {}", click_counter);
-                //1547391 + 53524 + 1587876 + 1588045
-                sleep(Duration::from_millis(1000));
+                checked = Some(Instant::now());
             }
+            // A golden cookie click is waiting for the mouse; let it have it.
+            if controller.mouse_wanted_first() {
+                sleep(combo.click_delay(config));
+                continue;
+            }
+            // This waits out any purchase in progress, which puts the cursor back
+            // on the cookie when it's done.
+            controller.click_cookie();
+            if click_counter < u128::MAX - 100 {
+                click_counter += 1;
+            } else if !send_message {
+                println!("reached max value at {}", Utc::now());
+                send_message = true;
+            }
+            sleep(combo.click_delay(config));
+        } else {
+            println!("click count: {}", click_counter);
+            //1547391 + 53524 + 1587876 + 1588045
+            sleep(Duration::from_millis(1000));
         }
     }
 }
 
-fn check_buildings(screenshot: &Screenshot, enigo: &mut Enigo, mut counter: u32) {
-    if counter > 20 {
-        print!("counter reached 20!");
+/// Buys every affordable building, most expensive first. Affordability comes from the
+/// store rows when they could be read, and from one pixel per row otherwise.
+fn check_buildings(
+    screenshot: &Screenshot,
+    rows: Option<&[StoreRow]>,
+    layout: &Layout,
+    config: &BuildingConfig,
+    input: &mut dyn InputSink,
+    mut counter: u32,
+) {
+    if counter > config.max_passes {
+        print!("counter reached {}!", config.max_passes);
         return;
     }
     counter += 1;
     println!("check buildings");
-    unsafe { CURSOR_ON_COOKIE = false; }
     let mut can_buy = false;
-    if compare_colors(check_pixel_color(&screenshot, 1692, 786), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1692, 786);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"alchemy lab\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1691, 725), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1691, 725);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"shipment\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1685, 659), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1685, 659);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"wizard tower\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1681, 595), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1681, 595);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"temple\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1677, 534), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1677, 534);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"bank\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1677, 470), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1677, 470);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"factory\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1694, 398), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1694, 398);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"mine\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1677, 344), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1677, 344);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"farm\")");
-    }
-    if compare_colors(check_pixel_color(&screenshot, 1723, 279), (255, 255, 255), 2) {
-        enigo.mouse_move_to(1723, 279);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"grandma\")");
-    }
-    println!("cursor color: {:?}", check_pixel_color(&screenshot, 1692, 215));
-    if compare_colors(check_pixel_color(&screenshot, 1692, 215), (255, 255, 255), 10) {
-        enigo.mouse_move_to(1692, 215);
-        enigo.mouse_click(MouseButton::Left);
-        can_buy = true;
-        println!("bought (\"cursor\")");
-    }
-
-    enigo.mouse_move_to(287, 417);
-    unsafe { CURSOR_ON_COOKIE = true; }
+    for &(building, x, y) in layout.building_probes.iter().rev() {
+        let row = rows.and_then(|rows| rows.iter().find(|row| row.building == building));
+        let (affordable, reason) = match row {
+            Some(row) => (row.affordable, price_reason(screenshot, row)),
+            None => match check_pixel_color(screenshot, x, y) {
+                Some(colour) => (compare_colors(colour, config.row_colour, config.tolerance(building)),
+                                 Reason::new(format!("{}'s name is lit", building)).with_colour(colour, (x, y))),
+                None => (false, Reason::new(format!("{}'s probe is off the screen", building))),
+            },
+        };
+        if affordable {
+            input.because(reason);
+            input.move_to(x, y);
+            input.click();
+            can_buy = true;
+            match row.and_then(|row| row.price) {
+                Some(price) => println!("bought (\"{}\") for {}", building, price),
+                None => println!("bought (\"{}\")", building),
+            }
+        }
+    }
 
     if can_buy {
-        check_buildings(screenshot, enigo, counter);
+        check_buildings(screenshot, rows, layout, config, input, counter);
+    }
+}
+
+/// Why `row` is bought: its price is in green, as seen on a pixel of the price.
+fn price_reason(screenshot: &Screenshot, row: &StoreRow) -> Reason {
+    let price = row.price.map(|price| price.to_string()).unwrap_or_default();
+    let reason = Reason::new(format!("{} costs {}, in green", row.building, price));
+    match row.price_ink.and_then(|(x, y)| check_pixel_color(screenshot, x, y).map(|colour| (colour, (x, y)))) {
+        Some((colour, at)) => reason.with_colour(colour, at),
+        None => reason,
     }
 }
 
-fn check_upgrades(screenshot: &Screenshot, enigo: &mut Enigo) {
-    unsafe { CURSOR_ON_COOKIE = false; }
-    if compare_colors(check_pixel_color(screenshot, 1845, 85), (230, 190, 148), 1) {
-        enigo.mouse_move_to(1845 + 10, 85 + 10);
-        enigo.mouse_click(MouseButton::Left);
-        println!("bought (\"fifth upgrade\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1785, 85), (230, 190, 148), 1) {
-        enigo.mouse_move_to(1785 + 10, 85 + 10);
-        enigo.mouse_click(MouseButton::Left);
-        println!("bought (\"forth upgrade\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1725, 85), (230, 190, 148), 1) {
-        enigo.mouse_move_to(1725 + 10, 85 + 10);
-        enigo.mouse_click(MouseButton::Left);
-        println!("bought (\"third upgrade\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1665, 85), (230, 190, 148), 1) {
-        enigo.mouse_move_to(1665 + 10, 85 + 10);
-        enigo.mouse_click(MouseButton::Left);
-        println!("bought (\"second upgrade\")");
-    }
-    if compare_colors(check_pixel_color(screenshot, 1605, 85), (230, 190, 148), 1) {
-        enigo.mouse_move_to(1605 + 10, 85 + 10);
-        enigo.mouse_click(MouseButton::Left);
-        println!("bought (\"first upgrade\")");
-    }
-    enigo.mouse_move_to(287, 417);
-    unsafe { CURSOR_ON_COOKIE = true; }
+/// Wheel notches that take the store from the top to the bottom of its building list.
+const STORE_SCROLL: i32 = 15;
+
+/// Scrolls the store down to the buildings past Alchemy lab, buys the affordable ones
+/// and scrolls back to the top, where `check_upgrades` expects the upgrade tray.
+/// Without the store templates the rows are told apart by where they'd be with every
+/// building unlocked, and bought if their name is lit.
+/// Golden cookies can have the mouse while the store scrolls, nobody else can.
+fn check_buildings_below_fold<'a>(
+    controller: &'a Controller,
+    mut input: MouseGuard<'a>,
+    capturer: &mut dyn CaptureSource,
+    store: Option<&StoreDetector>,
+    layout: &Layout,
+    config: &BuildingConfig,
+) -> MouseGuard<'a> {
+    let (x, y) = layout.store_centre();
+    input.because(Reason::new("scrolling down to the buildings past Alchemy lab"));
+    input.move_to(x, y);
+    input.scroll(STORE_SCROLL);
+    // Let the smooth scroll finish before looking.
+    input = controller.hold(input, Duration::from_millis(500));
+
+    match capturer.get_screenshot() {
+        Ok(screenshot) => {
+            let affordable = match store {
+                Some(store) => {
+                    let rows = store.scan_rows(&screenshot, layout);
+                    println!("visible after scrolling: {:?}", rows.iter().map(|row| row.building).collect::<Vec<_>>());
+                    rows.into_iter()
+                        .filter(|row| row.affordable)
+                        .map(|row| {
+                            let reason = price_reason(&screenshot, &row);
+                            (row, reason)
+                        })
+                        .collect()
+                }
+                None => lit_rows_below_fold(&screenshot, layout, config),
+            };
+            for (row, reason) in affordable.into_iter().rev().filter(|(row, _)| row.building > Building::AlchemyLab) {
+                input.because(reason);
+                input.move_to(row.position.0, row.position.1);
+                input.click();
+                match row.price {
+                    Some(price) => println!("bought (\"{}\") for {}", row.building, price),
+                    None => println!("bought (\"{}\")", row.building),
+                }
+            }
+        }
+        Err(err) => println!("couldn't look at the scrolled store: {}", err),
+    }
+
+    // Overshooting is harmless, the panel stops at the top.
+    input.because(Reason::new("scrolling the store back to the top"));
+    input.move_to(x, y);
+    input.scroll(-2 * STORE_SCROLL);
+    controller.hold(input, Duration::from_millis(500))
+}
+
+/// The rows past Alchemy lab in a store scrolled to the bottom whose name has a pixel
+/// of `config.row_colour`, found where `Layout::scrolled_row_top` puts them.
+fn lit_rows_below_fold(screenshot: &Screenshot, layout: &Layout, config: &BuildingConfig) -> Vec<(StoreRow, Reason)> {
+    let below_fold = Building::ALL.iter().filter(|&&building| building > Building::AlchemyLab);
+    below_fold
+        .filter_map(|&building| {
+            let top = layout.scrolled_row_top(building.store_order())?;
+            let band = layout.name_band(top);
+            let lit = (band.y..band.bottom())
+                .flat_map(|y| (band.x..band.right()).map(move |x| (x as i32, y as i32)))
+                .find_map(|(x, y)| {
+                    check_pixel_color(screenshot, x, y)
+                        .filter(|&colour| compare_colors(colour, config.row_colour, config.tolerance(building)))
+                        .map(|colour| (colour, (x, y)))
+                })?;
+            let row = StoreRow {
+                building,
+                price: None,
+                owned: None,
+                affordable: true,
+                price_ink: None,
+                position: layout.row_position(top),
+            };
+            let reason = Reason::new(format!("{}'s name is lit", building)).with_colour(lit.0, lit.1);
+            Some((row, reason))
+        })
+        .collect()
+}
+
+/// Looks for golden cookies about every `config.reaction()` and clicks each one
+/// it finds ahead of the auto clicker, and each wrath cookie the policy allows.
+/// Returns if the screen can't be captured.
+fn click_golden_cookies(controller: &Controller, detector: &ShimmerDetector, capturer: &mut dyn CaptureSource, config: &GoldenConfig) {
+    // Wrath cookies left alone last time, so each is only logged once.
+    let mut skipped: Vec<(i32, i32)> = vec![];
+    loop {
+        if !controller.is_running() {
+            sleep(Duration::from_millis(100));
+            continue;
+        }
+        let started = Instant::now();
+        let screenshot = match capture(capturer) {
+            Ok(screenshot) => screenshot,
+            Err(err) => {
+                println!("stopped looking for golden cookies: {}", err);
+                return;
+            }
+        };
+        let clot = controller.state().is_active(Buff::Clot);
+        let mut to_click = vec![];
+        let mut to_skip = vec![];
+        let mut still_skipped = vec![];
+        for shimmer in detector.find(&screenshot, &controller.layout()) {
+            let (click, why) = config.wrath.decide(shimmer.kind, clot);
+            if click {
+                to_click.push((shimmer, why));
+                continue;
+            }
+            let near = |&(x, y): &(i32, i32)| (x - shimmer.centre.0).abs() + (y - shimmer.centre.1).abs() < shimmer.diameter as i32;
+            if !skipped.iter().any(near) {
+                to_skip.push((shimmer, why));
+            }
+            still_skipped.push(shimmer.centre);
+        }
+        skipped = still_skipped;
+
+        if !to_click.is_empty() || !to_skip.is_empty() {
+            let mut mouse = controller.take_mouse_first();
+            for (shimmer, why) in &to_skip {
+                let reason = Reason::new(format!("{}, {} across, not clicking: {}", shimmer.kind, shimmer.diameter, why))
+                    .with_colour(shimmer.colour, shimmer.centre);
+                mouse.skip(shimmer.centre.0, shimmer.centre.1, reason);
+            }
+            for (shimmer, why) in &to_click {
+                mouse.because(Reason::new(format!("{}, {} across, clicking: {}", shimmer.kind, shimmer.diameter, why))
+                    .with_colour(shimmer.colour, shimmer.centre));
+                mouse.move_to(shimmer.centre.0, shimmer.centre.1);
+                mouse.click();
+                println!("clicked a {} at {:?}", shimmer.kind, shimmer.centre);
+            }
+        }
+
+        // A cookie that shows up right after a capture is seen one sleep and one
+        // scan later, so the two together have to fit in the reaction time.
+        let scan = started.elapsed();
+        if scan >= config.reaction() {
+            println!("looking for golden cookies took {:?}, longer than the reaction time", scan);
+        }
+        sleep(config.reaction().saturating_sub(scan));
+    }
+}
+
+/// Clicks `lump` if it's time. `live` is false in dry runs, where the click is
+/// only logged and nothing is counted as harvested.
+fn harvest_lump(lump: &Lump, harvester: &mut Harvester, input: &mut dyn InputSink, live: bool) {
+    if !harvester.should_harvest(lump) {
+        return;
+    }
+    input.because(Reason::new(format!("the sugar lump is {}, {:.0}% like the template", lump.stage, lump.score * 100.0)));
+    input.move_to(lump.centre.0, lump.centre.1);
+    input.click();
+    if live {
+        harvester.record();
+        println!("harvested a sugar lump at {}, {} since starting", Utc::now(), harvester.harvested);
+    } else {
+        harvester.wait();
+    }
+}
+
+fn check_upgrades(screenshot: &Screenshot, layout: &Layout, config: &UpgradeConfig, input: &mut dyn InputSink) {
+    const NAMES: [&str; 5] = ["first", "second", "third", "forth", "fifth"];
+    // The slots are probed on the frame's edge; the click goes a little way into it.
+    let inset = (10.0 * layout.scale()).round() as i32;
+    for (slot, &(x, y)) in layout.upgrade_slots.iter().enumerate().rev() {
+        let colour = check_pixel_color(screenshot, x, y)
+            .filter(|&colour| compare_colors(colour, config.colour, config.tolerance));
+        if let Some(colour) = colour {
+            input.because(Reason::new(format!("{} upgrade slot has an affordable upgrade", NAMES.get(slot).unwrap_or(&"another")))
+                .with_colour(colour, (x, y)));
+            input.move_to(x + inset, y + inset);
+            input.click();
+            println!("bought (\"{} upgrade\")", NAMES.get(slot).unwrap_or(&"another"));
+        }
+    }
 }
 
 fn compare_colors((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8), tolerance: u8) -> bool {
@@ -259,19 +625,189 @@ fn compare_colors((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8), tolera
     && (g1 as i16 - g2 as i16).abs() <= tolerance as i16
 }
 
-fn check_pixel_color(screenshot: &Screenshot, x: i32, y: i32) -> (u8, u8, u8) {
-    if x < 0 || y < 0 {
-        return (0, 0, 0)
-    }
-    let pixel = screenshot.get_pixel(y as usize, x as usize);
-    (pixel.r, pixel.g, pixel.b)
+/// Colour at (x, y) from the top left of the screen, `None` off it.
+fn check_pixel_color(screenshot: &Screenshot, x: i32, y: i32) -> Option<(u8, u8, u8)> {
+    let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+    screenshot.pixel(x, y).map(|pixel| pixel.rgb())
 }
 
-fn bind_keybinds() {
-    YKey.bind(|| {
-        unsafe {
-            RUNNING = !RUNNING;
-            println!("{}", RUNNING);
-        }
+fn bind_keybinds(controller: &Arc<Controller>) {
+    let controller = controller.clone();
+    YKey.bind(move || {
+        println!("{}", controller.toggle_running());
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::input::RecordingSink;
+    use crate::notation::CookieAmount;
+    use std::collections::VecDeque;
+
+    const BACKGROUND: Rgba<u8> = Rgba([20, 40, 90, 255]);
+
+    /// A 1080p frame with `colour` painted at each of `points`.
+    fn frame(colour: (u8, u8, u8), points: &[(i32, i32)]) -> Screenshot {
+        let mut image = RgbaImage::from_pixel(1920, 1080, BACKGROUND);
+        for &(x, y) in points {
+            image.put_pixel(x as u32, y as u32, Rgba([colour.0, colour.1, colour.2, 255]));
+        }
+        Screenshot::from(&image)
+    }
+
+    fn probe(layout: &Layout, building: Building) -> (i32, i32) {
+        let &(_, x, y) = layout.building_probes.iter().find(|(probe, _, _)| *probe == building).unwrap();
+        (x, y)
+    }
+
+    #[test]
+    fn buys_buildings_whose_names_are_lit() {
+        let layout = Layout::default();
+        let config = BuildingConfig { max_passes: 1, ..BuildingConfig::default() };
+        let (farm, cursor) = (probe(&layout, Building::Farm), probe(&layout, Building::Cursor));
+        let screenshot = frame((254, 254, 254), &[farm, cursor]);
+
+        let mut input = RecordingSink::new();
+        check_buildings(&screenshot, None, &layout, &config, &mut input, 0);
+        // The most expensive first, and once more for the pass that goes back over the store.
+        assert_eq!(input.clicks(), vec![farm, cursor, farm, cursor]);
+    }
+
+    #[test]
+    fn buys_nothing_when_nothing_is_lit() {
+        let layout = Layout::default();
+        let mut input = RecordingSink::new();
+        check_buildings(&frame((0, 0, 0), &[]), None, &layout, &BuildingConfig::default(), &mut input, 0);
+        assert!(input.actions().is_empty());
+    }
+
+    #[test]
+    fn probes_the_names_of_scrolled_rows_without_store_templates() {
+        let layout = Layout::default();
+        let name = |building: Building| {
+            let band = layout.name_band(layout.scrolled_row_top(building.store_order()).unwrap());
+            ((band.x + 30) as i32, (band.y + 5) as i32)
+        };
+        // Alchemy lab was already checked before scrolling.
+        let lit = [name(Building::AlchemyLab), name(Building::Portal), name(Building::Chancemaker)];
+        let screenshot = frame((255, 255, 255), &lit);
+
+        let rows = lit_rows_below_fold(&screenshot, &layout, &BuildingConfig::default());
+        let found: Vec<_> = rows.iter().map(|(row, _)| (row.building, row.position)).collect();
+        let position = |building: Building| layout.row_position(layout.scrolled_row_top(building.store_order()).unwrap());
+        assert_eq!(found, vec![
+            (Building::Portal, position(Building::Portal)),
+            (Building::Chancemaker, position(Building::Chancemaker)),
+        ]);
+        assert_eq!(layout.scrolled_row_top(Building::ALL.len() - 1), Some(layout.store.bottom() - layout.row_height));
+    }
+
+    #[test]
+    fn goes_by_the_store_rows_when_they_were_read() {
+        let layout = Layout::default();
+        let config = BuildingConfig { max_passes: 1, ..BuildingConfig::default() };
+        let grandma = probe(&layout, Building::Grandma);
+        // The farm's probe is lit, but its row was read as too expensive.
+        let screenshot = frame((255, 255, 255), &[probe(&layout, Building::Farm)]);
+        let row = |building: Building, affordable: bool| StoreRow {
+            building,
+            price: Some(CookieAmount(building.price(0).value())),
+            owned: Some(0),
+            affordable,
+            price_ink: None,
+            position: (0, 0),
+        };
+        let rows = [row(Building::Grandma, true), row(Building::Farm, false)];
+
+        let mut input = RecordingSink::new();
+        check_buildings(&screenshot, Some(&rows), &layout, &config, &mut input, 0);
+        assert_eq!(input.clicks(), vec![grandma, grandma]);
+    }
+
+    #[test]
+    fn buys_affordable_upgrades_from_the_right() {
+        let layout = Layout::default();
+        let config = UpgradeConfig::default();
+        let (first, third) = (layout.upgrade_slots[0], layout.upgrade_slots[2]);
+        let screenshot = frame(config.colour, &[first, third]);
+
+        let mut input = RecordingSink::new();
+        check_upgrades(&screenshot, &layout, &config, &mut input);
+        assert_eq!(input.clicks(), vec![(third.0 + 10, third.1 + 10), (first.0 + 10, first.1 + 10)]);
+    }
+
+    /// Fails with `errors`, in order, then captures blank frames. Notes after how
+    /// many attempts it was reset.
+    struct Flaky {
+        errors: VecDeque<CaptureError>,
+        attempts: usize,
+        resets: Vec<usize>,
+    }
+
+    impl Flaky {
+        fn new(errors: Vec<CaptureError>) -> Self {
+            Self { errors: errors.into(), attempts: 0, resets: vec![] }
+        }
+    }
+
+    impl CaptureSource for Flaky {
+        fn get_screenshot(&mut self) -> Result<Screenshot, CaptureError> {
+            self.attempts += 1;
+            match self.errors.pop_front() {
+                Some(err) => Err(err),
+                None => Ok(Screenshot::from(&RgbaImage::new(4, 4))),
+            }
+        }
+
+        fn reset(&mut self) {
+            self.resets.push(self.attempts);
+        }
+    }
+
+    const GRAB: CaptureError = CaptureError::Grab { what: "grab failed", code: None };
+    const INIT: CaptureError = CaptureError::Init { what: "init failed", code: None };
+
+    fn millis(waits: &[u64]) -> Vec<Duration> {
+        waits.iter().map(|&ms| Duration::from_millis(ms)).collect()
+    }
+
+    #[test]
+    fn backs_off_and_starts_over_every_few_failures() {
+        let mut source = Flaky::new(vec![GRAB; 7]);
+        let mut waits = vec![];
+        assert!(capture_waiting(&mut source, |wait| waits.push(wait)).is_ok());
+        assert_eq!(waits, millis(&[250, 500, 1_000, 2_000, 4_000, 8_000, 16_000]));
+        assert_eq!(source.resets, vec![REINIT_AFTER as usize, 2 * REINIT_AFTER as usize]);
+        assert_eq!(source.attempts, 8);
+    }
+
+    #[test]
+    fn waits_no_longer_than_a_minute() {
+        let mut source = Flaky::new(vec![GRAB; 10]);
+        let mut waits = vec![];
+        capture_waiting(&mut source, |wait| waits.push(wait)).unwrap();
+        assert_eq!(waits[7..], [Duration::from_secs(32), MAX_CAPTURE_BACKOFF, MAX_CAPTURE_BACKOFF]);
+    }
+
+    #[test]
+    fn starts_over_right_away_after_an_init_error() {
+        let mut source = Flaky::new(vec![INIT, GRAB, INIT]);
+        capture_waiting(&mut source, |_| {}).unwrap();
+        assert_eq!(source.resets, vec![1, 3]);
+    }
+
+    #[test]
+    fn gives_up_on_errors_that_wont_clear() {
+        let mut source = Flaky::new(vec![GRAB, GRAB, CaptureError::NoSuchScreen(3), GRAB]);
+        let mut waits = vec![];
+        let err = capture_waiting(&mut source, |wait| waits.push(wait)).err();
+        assert_eq!(err, Some(CaptureError::NoSuchScreen(3)));
+        assert_eq!(waits, millis(&[250, 500]));
+        assert_eq!(source.attempts, 3);
+
+        let mut source = Flaky::new(vec![CaptureError::ReplayEnded]);
+        assert_eq!(capture_waiting(&mut source, |_| panic!("waited")).err(), Some(CaptureError::ReplayEnded));
+    }
+}
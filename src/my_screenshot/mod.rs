@@ -1,25 +1,47 @@
 //! Capture a bitmap image of a display. The resulting my_screenshot is stored in
 //! the `Screenshot` type, which varies per platform.
 //!
+//! Each platform hands the bitmap over in its own layout, which the screenshot
+//! records as an `Origin` and a `PixelFormat` instead of copying it around.
+//! `Screenshot::pixel` and everything built on it take coordinates from the top
+//! left corner of the screen, whatever the layout underneath.
+//!
 //! # Platform-specific details
 //!
 //! Despite OS X's CoreGraphics documentation, the bitmap returned has its
-//! origin at the top left corner. It uses ARGB pixels.
+//! origin at the top left corner. It uses ARGB pixels, which are B, G, R, A in
+//! memory.
+//!
+//! The Windows GDI bitmap has its coordinate origin at the bottom left, and
+//! its pixels are B, G, R with an unused fourth byte.
 //!
-//! The Windows GDI bitmap has its coordinate origin at the bottom left. We
-//! attempt to undo this by reordering the rows. Windows also uses ARGB pixels.
+//! On Linux we talk to the X server directly. The root window is grabbed with
+//! XShm when the extension is available and with plain `XGetImage` otherwise.
+//! X11 images are top-down, with the same pixels as GDI on a little endian server.
+//! Monitors come from RandR; without it the whole root window is one monitor.
 //!
-//! TODO Linux support. Contributions welcome.
+//! Windows and Linux capture one monitor of the desktop at a time, numbered as
+//! `monitors` lists them.
 
 
-#![feature(core_intrinsics)]
 #![allow(unused_assignments)]
 
 extern crate libc;
 
-use std::intrinsics::{size_of, offset};
+pub mod replay;
+
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::slice::ChunksExact;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Pixel {
     pub a: u8,
     pub r: u8,
@@ -27,14 +49,72 @@ pub struct Pixel {
     pub b: u8,
 }
 
+impl Pixel {
+    #[inline]
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        (self.r, self.g, self.b)
+    }
+}
+
+/// Which corner of the screen the first row of the bitmap belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    TopLeft,
+    BottomLeft,
+}
+
+/// Order of the bytes of one pixel in the bitmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// What GDI, X11 and CoreGraphics hand over. The fourth byte, if there is
+    /// one, is alpha; GDI and X11 leave it zeroed.
+    Bgra,
+    Rgba,
+}
+
+impl PixelFormat {
+    fn decode(self, bytes: &[u8]) -> Pixel {
+        let a = bytes.get(3).copied().unwrap_or(255);
+        match self {
+            PixelFormat::Bgra => Pixel { a, r: bytes[2], g: bytes[1], b: bytes[0] },
+            PixelFormat::Rgba => Pixel { a, r: bytes[0], g: bytes[1], b: bytes[2] },
+        }
+    }
+}
+
+/// A rectangle of a my_screenshot, in pixels from the top left corner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Exclusive right edge.
+    #[inline]
+    pub fn right(&self) -> usize { self.x + self.width }
+
+    /// Exclusive bottom edge.
+    #[inline]
+    pub fn bottom(&self) -> usize { self.y + self.height }
+}
+
 /// An image buffer containing the my_screenshot.
-/// Pixels are stored as [ARGB](https://en.wikipedia.org/wiki/ARGB).
+/// How the bytes are laid out is given by `origin` and `format`.
 pub struct Screenshot {
     pub data: Vec<u8>,
     height: usize,
     width: usize,
     row_len: usize, // Might be superfluous
     pixel_width: usize,
+    origin: Origin,
+    format: PixelFormat,
 }
 
 impl Screenshot {
@@ -54,13 +134,29 @@ impl Screenshot {
     #[inline]
     pub fn pixel_width(&self) -> usize { self.pixel_width }
 
+    /// Where the first row of bitmap is on screen.
+    #[inline]
+    pub fn origin(&self) -> Origin { self.origin }
+
+    /// Byte order of a pixel in bitmap.
+    #[inline]
+    pub fn format(&self) -> PixelFormat { self.format }
+
     /// Raw bitmap.
+    ///
+    /// # Safety
+    ///
+    /// The pointer is only valid for `raw_len()` bytes, and only while the screenshot is alive.
     #[inline]
     pub unsafe fn raw_data(&self) -> *const u8 {
         &self.data[0] as *const u8
     }
 
     /// Raw bitmap.
+    ///
+    /// # Safety
+    ///
+    /// As for `raw_data`, and nothing else may read the screenshot while the pointer is written through.
     #[inline]
     pub unsafe fn raw_data_mut(&mut self) -> *mut u8 {
         &mut self.data[0] as *mut u8
@@ -69,24 +165,126 @@ impl Screenshot {
     /// Number of bytes in bitmap
     #[inline]
     pub fn raw_len(&self) -> usize {
-        self.data.len() * unsafe {size_of::<u8>()}
+        self.data.len()
     }
 
-    /// Gets pixel at (row, col)
-    pub fn get_pixel(&self, mut y: usize, mut x: usize) -> Pixel {
-        y = (y as i32 - self.height as i32).abs() as usize + 2;
-        let idx = (y *self.row_len() + x *self.pixel_width()) as isize;
-        unsafe {
-            let data = &self.data[0] as *const u8;
-            if idx as usize > self.raw_len() { panic!("Bounds overflow"); }
-
-            Pixel {
-                a: *offset(data, idx+3),
-                r: *offset(data, idx+2),
-                g: *offset(data, idx+1),
-                b: *offset(data, idx),
-            }
+    /// Pixel at (x, y) from the top left corner, or `None` off the image.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.width {
+            return None;
         }
+        let start = x * self.pixel_width;
+        let bytes = self.row_bytes(y)?.get(start..start + self.pixel_width)?;
+        Some(self.format.decode(bytes))
+    }
+
+    /// Pixels of row `y`, left to right.
+    pub fn row(&self, y: usize) -> Option<Pixels<'_>> {
+        let bytes = self.row_bytes(y)?;
+        Some(Pixels { chunks: bytes.chunks_exact(self.pixel_width), format: self.format })
+    }
+
+    /// Every row, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = Pixels<'_>> + '_ {
+        (0..self.height).filter_map(move |y| self.row(y))
+    }
+
+    /// A view of `region`, or `None` unless it lies entirely inside the image.
+    pub fn region(&self, region: Region) -> Option<SubImage<'_>> {
+        if region.right() > self.width || region.bottom() > self.height {
+            return None;
+        }
+        Some(SubImage { screenshot: self, region })
+    }
+
+    /// The whole image as a view.
+    pub fn view(&self) -> SubImage<'_> {
+        SubImage { screenshot: self, region: Region::new(0, 0, self.width, self.height) }
+    }
+
+    /// Bytes of row `y` counted from the top, without any padding at the end.
+    fn row_bytes(&self, y: usize) -> Option<&[u8]> {
+        if y >= self.height {
+            return None;
+        }
+        let stored = match self.origin {
+            Origin::TopLeft => y,
+            Origin::BottomLeft => self.height - 1 - y,
+        };
+        let start = stored * self.row_len;
+        self.data.get(start..start + self.width * self.pixel_width)
+    }
+}
+
+/// Pixels of one row, left to right.
+#[derive(Clone)]
+pub struct Pixels<'a> {
+    chunks: ChunksExact<'a, u8>,
+    format: PixelFormat,
+}
+
+impl<'a> Iterator for Pixels<'a> {
+    type Item = Pixel;
+
+    fn next(&mut self) -> Option<Pixel> {
+        self.chunks.next().map(|bytes| self.format.decode(bytes))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Pixels<'a> {}
+
+/// A rectangle of a screenshot, addressed from its own top left corner.
+#[derive(Clone, Copy)]
+pub struct SubImage<'a> {
+    screenshot: &'a Screenshot,
+    region: Region,
+}
+
+impl<'a> SubImage<'a> {
+    #[inline]
+    pub fn width(&self) -> usize { self.region.width }
+
+    #[inline]
+    pub fn height(&self) -> usize { self.region.height }
+
+    /// Where the view is on the screenshot it came from.
+    #[inline]
+    pub fn bounds(&self) -> Region { self.region }
+
+    /// Pixel at (x, y) from the view's top left corner, or `None` outside it.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.region.width || y >= self.region.height {
+            return None;
+        }
+        self.screenshot.pixel(self.region.x + x, self.region.y + y)
+    }
+
+    /// Every row of the view, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = Pixels<'a>> + 'a {
+        let (screenshot, region) = (self.screenshot, self.region);
+        (region.y..region.bottom()).filter_map(move |y| {
+            let bytes = screenshot.row_bytes(y)?;
+            let range = region.x * screenshot.pixel_width..region.right() * screenshot.pixel_width;
+            Some(Pixels { chunks: bytes[range].chunks_exact(screenshot.pixel_width), format: screenshot.format })
+        })
+    }
+
+    /// Every pixel of the view with its position in it, row by row.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, Pixel)> + 'a {
+        self.rows().enumerate().flat_map(|(y, row)| row.enumerate().map(move |(x, pixel)| (x, y, pixel)))
+    }
+
+    /// A smaller view, with `region` relative to this one.
+    pub fn region(&self, region: Region) -> Option<SubImage<'a>> {
+        if region.right() > self.region.width || region.bottom() > self.region.height {
+            return None;
+        }
+        let region = Region { x: self.region.x + region.x, y: self.region.y + region.y, ..region };
+        Some(SubImage { screenshot: self.screenshot, region })
     }
 }
 
@@ -97,7 +295,203 @@ impl AsRef<[u8]> for Screenshot {
     }
 }
 
-pub type ScreenResult = Result<Screenshot, &'static str>;
+/// Top row first, as the frame looks on screen, with channels in RGBA order.
+impl From<&Screenshot> for RgbaImage {
+    fn from(screenshot: &Screenshot) -> Self {
+        let mut image = RgbaImage::new(screenshot.width as u32, screenshot.height as u32);
+        for (y, row) in screenshot.rows().enumerate() {
+            for (x, pixel) in row.enumerate() {
+                image.put_pixel(x as u32, y as u32, Rgba([pixel.r, pixel.g, pixel.b, pixel.a]));
+            }
+        }
+        image
+    }
+}
+
+/// Keeps the image's own layout: RGBA pixels, top row first.
+impl From<&RgbaImage> for Screenshot {
+    fn from(image: &RgbaImage) -> Self {
+        let pixel_width: usize = 4;
+        let width = image.width() as usize;
+
+        Screenshot {
+            data: image.as_raw().clone(),
+            height: image.height() as usize,
+            width,
+            row_len: width * pixel_width,
+            pixel_width,
+            origin: Origin::TopLeft,
+            format: PixelFormat::Rgba,
+        }
+    }
+}
+
+/// What `Screenshot::save_png` writes into the file besides the pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub captured_at: DateTime<Utc>,
+    /// Screen the frame came from, if it came from one.
+    pub screen: Option<usize>,
+}
+
+impl FrameInfo {
+    /// A frame captured just now from `screen`.
+    pub fn now(screen: Option<usize>) -> Self {
+        Self { captured_at: Utc::now(), screen }
+    }
+}
+
+impl Screenshot {
+    /// Writes the frame to a PNG, with `info` in text chunks that image viewers
+    /// show as properties. The alpha channel is left out: GDI leaves it zeroed,
+    /// which would make the picture invisible.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, info: &FrameInfo) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Creation Time".to_string(), info.captured_at.to_rfc2822())?;
+        if let Some(screen) = info.screen {
+            encoder.add_text_chunk("Screen".to_string(), screen.to_string())?;
+        }
+        encoder.add_text_chunk("Software".to_string(), "cookie_monster".to_string())?;
+
+        let image = RgbaImage::from(self);
+        let rgb: Vec<u8> = image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+        let mut writer = encoder.write_header().with_context(|| format!("writing {}", path.display()))?;
+        writer.write_image_data(&rgb).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// One monitor, placed on the desktop the mouse moves over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Monitor {
+    /// Top left corner in the coordinates the mouse moves in: from the primary
+    /// monitor's corner on Windows, so negative left of or above it, and from the
+    /// root window's on X.
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+    pub primary: bool,
+}
+
+impl Monitor {
+    /// Where (0, 0) of a screenshot of this monitor is on the desktop.
+    #[inline]
+    pub fn origin(&self) -> (i32, i32) { (self.x, self.y) }
+}
+
+/// Every monitor, the primary one first and the rest left to right, then top to
+/// bottom. A monitor's place in the list is the screen number `Capturer` takes.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub fn monitors() -> Result<Vec<Monitor>, CaptureError> {
+    Ok(in_order(ffi::monitors()?))
+}
+
+/// How often a capturer looks at the monitors again, to notice one that was moved,
+/// resized or unplugged. Listing them takes a round trip to the display server.
+const MONITOR_CHECK: Duration = Duration::from_secs(2);
+
+fn in_order(mut monitors: Vec<Monitor>) -> Vec<Monitor> {
+    monitors.sort_by_key(|monitor| (!monitor.primary, monitor.x, monitor.y));
+    monitors
+}
+
+/// Why a screenshot couldn't be taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    /// Getting hold of the display, or of a buffer to copy it into, failed, or the
+    /// ones set up no longer fit the display. `code` is the OS error code (the X
+    /// error code on X), when there is one.
+    Init { what: &'static str, code: Option<i32> },
+    /// Copying a frame off a display that was set up fine failed.
+    Grab { what: &'static str, code: Option<i32> },
+    /// There's no monitor with that number.
+    NoSuchScreen(usize),
+    /// The OS handed over pixels in a layout that can't be read.
+    Unsupported(&'static str),
+    /// A replay frame, or the directory of them, couldn't be read.
+    Replay { path: PathBuf, reason: String },
+    /// A replay has handed out its last frame.
+    ReplayEnded,
+}
+
+impl CaptureError {
+    /// The OS error code behind the failure, if there is one.
+    pub fn os_code(&self) -> Option<i32> {
+        match self {
+            CaptureError::Init { code, .. } | CaptureError::Grab { code, .. } => *code,
+            _ => None,
+        }
+    }
+
+    /// Whether trying again later may work: the screen can be locked, in the
+    /// middle of a mode switch or the X server restarting. Everything else will
+    /// fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, CaptureError::Init { .. } | CaptureError::Grab { .. })
+    }
+
+    /// Whether the source has to be set up again before trying, because it may
+    /// have been left half initialised.
+    pub fn needs_reinit(&self) -> bool {
+        matches!(self, CaptureError::Init { .. })
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Init { what, code } | CaptureError::Grab { what, code } => {
+                write!(f, "{}", what)?;
+                if let Some(code) = code {
+                    write!(f, " (OS error {})", code)?;
+                }
+                Ok(())
+            }
+            CaptureError::NoSuchScreen(screen) => write!(f, "Screen {} doesn't exist.", screen),
+            CaptureError::Unsupported(what) => write!(f, "{}", what),
+            CaptureError::Replay { path, reason } => write!(f, "Can't replay {}: {}", path.display(), reason),
+            CaptureError::ReplayEnded => write!(f, "No more frames to replay."),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+pub type ScreenResult = Result<Screenshot, CaptureError>;
+
+/// Something that hands out screenshots, either a live display or saved frames.
+pub trait CaptureSource {
+    fn get_screenshot(&mut self) -> ScreenResult;
+
+    /// Lets go of whatever the source holds on to, so the next screenshot sets
+    /// it up again from scratch.
+    fn reset(&mut self) {}
+
+    /// Whether the display changed size or monitors since the last call, in which
+    /// case anything measured off earlier frames is stale.
+    fn display_changed(&mut self) -> bool { false }
+}
+
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+impl CaptureSource for ffi::Capturer {
+    fn get_screenshot(&mut self) -> ScreenResult {
+        ffi::Capturer::get_screenshot(self)
+    }
+
+    fn reset(&mut self) {
+        self.dispose();
+    }
+
+    fn display_changed(&mut self) -> bool {
+        ffi::Capturer::display_changed(self)
+    }
+}
 
 #[cfg(target_os = "macos")]
 mod ffi {
@@ -107,6 +501,8 @@ mod ffi {
     use libc;
     use ::Screenshot;
     use ::ScreenResult;
+    use ::CaptureError;
+    use ::{Origin, PixelFormat};
 
     type CFIndex = libc::c_long;
     type CFDataRef = *const u8; // *const CFData
@@ -158,7 +554,7 @@ type CGDataProviderRef = *mut u8; // *mut CGDataProvider
             let mut err = CGDisplayNoErr;
             err = CGGetActiveDisplayList(0, 0 as *mut CGDirectDisplayID, &mut count);
             if err != CGDisplayNoErr {
-                return Err("Error getting number of displays.");
+                return Err(CaptureError::Init { what: "Error getting number of displays.", code: Some(err) });
             }
 
             // Get list of displays
@@ -168,7 +564,7 @@ type CGDataProviderRef = *mut u8; // *mut CGDataProvider
                                          &mut disps[0] as *mut CGDirectDisplayID,
                                          &mut count);
             if err != CGDisplayNoErr {
-                return Err("Error getting list of displays.");
+                return Err(CaptureError::Init { what: "Error getting list of displays.", code: Some(err) });
             }
 
             // Get my_screenshot of requested display
@@ -181,7 +577,7 @@ type CGDataProviderRef = *mut u8; // *mut CGDataProvider
             let row_len = CGImageGetBytesPerRow(cg_img) as usize;
             let pixel_bits = CGImageGetBitsPerPixel(cg_img) as usize;
             if pixel_bits % 8 != 0 {
-                return Err("Pixels aren't integral bytes.");
+                return Err(CaptureError::Unsupported("Pixels aren't integral bytes."));
             }
 
             // Copy image into a Vec buffer
@@ -189,7 +585,7 @@ type CGDataProviderRef = *mut u8; // *mut CGDataProvider
             let raw_len = CFDataGetLength(cf_data) as usize;
 
             let res = if width*height*pixel_bits != raw_len*8 {
-                Err("Image size is inconsistent with W*H*D.")
+                Err(CaptureError::Unsupported("Image size is inconsistent with W*H*D."))
             } else {
                 let data = slice::from_raw_parts(CFDataGetBytePtr(cf_data), raw_len).to_vec();
                 Ok(Screenshot {
@@ -197,7 +593,9 @@ type CGDataProviderRef = *mut u8; // *mut CGDataProvider
                     height: height,
                     width: width,
                     row_len: row_len,
-                    pixel_width: pixel_bits/8
+                    pixel_width: pixel_bits/8,
+                    origin: Origin::TopLeft,
+                    format: PixelFormat::Bgra,
                 })
             };
 
@@ -215,9 +613,9 @@ pub mod ffi {
     #![allow(non_snake_case, dead_code)]
 
     use libc::{c_int, c_uint, c_long, c_void};
-    use std::intrinsics::{size_of};
+    use std::mem::size_of;
     use std::time::Instant;
-    use crate::my_screenshot::{ScreenResult, Screenshot};
+    use crate::my_screenshot::{CaptureError, Monitor, Origin, PixelFormat, ScreenResult, Screenshot};
 
     type PVOID = *mut c_void;
     type LPVOID = *mut c_void;
@@ -227,9 +625,10 @@ pub mod ffi {
     type BYTE = u8;
     type UINT = c_uint;
     type LONG = c_long;
-    type LPARAM = c_long;
+    type LPARAM = isize;
 
     #[repr(C)]
+    #[derive(Default)]
     struct RECT {
         left: LONG,
         top: LONG,
@@ -244,6 +643,7 @@ pub mod ffi {
     type HWND = HANDLE;
     type HDC = HANDLE;
     #[repr(C)]
+    #[derive(Default)]
     struct MONITORINFO {
         cbSize: DWORD,
         rcMonitor: RECT,
@@ -251,7 +651,7 @@ pub mod ffi {
         dwFlags: DWORD,
     }
     type LPMONITORINFO = *mut MONITORINFO;
-    type MONITORENUMPROC = fn(HMONITOR, HDC, LPRECT, LPARAM) -> BOOL;
+    type MONITORENUMPROC = unsafe extern "system" fn(HMONITOR, HDC, LPRECT, LPARAM) -> BOOL;
 
     type HBITMAP = HANDLE;
     type HGDIOBJ = HANDLE;
@@ -261,6 +661,7 @@ pub mod ffi {
     const HGDI_ERROR: *mut c_void = -1isize as *mut c_void;
     const SM_CXSCREEN: c_int = 0;
     const SM_CYSCREEN: c_int = 1;
+    const MONITORINFOF_PRIMARY: DWORD = 1;
 
     /// TODO verify value
     const SRCCOPY: u32 = 0x00CC0020;
@@ -307,6 +708,11 @@ pub mod ffi {
         h_dc: HDC,
         h_bmp: HBITMAP,
         h_wnd_screen: HWND,
+        /// The monitor as it was when last loaded. Kept across `dispose`.
+        monitor: Option<Monitor>,
+        /// When the monitors were last listed.
+        checked: Instant,
+        changed: bool,
     }
 
     impl Capturer {
@@ -320,36 +726,65 @@ pub mod ffi {
                 h_dc: NULL,
                 h_bmp: NULL,
                 h_wnd_screen: NULL,
+                monitor: None,
+                checked: Instant::now(),
+                changed: false,
             }
         }
 
+        /// True once after the display came back different from how it was loaded.
+        pub fn display_changed(&mut self) -> bool {
+            std::mem::replace(&mut self.changed, false)
+        }
+
         pub fn get_screenshot(&mut self) -> ScreenResult {
             unsafe {
-                // Enumerate monitors, getting a handle and DC for requested monitor.
-                // loljk, because doing that on Windows is worse than death
+                // The desktop DC spans every monitor; the one asked for is copied out of
+                // it. The buffer is only as big as the monitor was when it was made, so
+                // it's made again when the monitor changes.
+                if self.loaded && self.checked.elapsed() >= super::MONITOR_CHECK {
+                    self.checked = Instant::now();
+                    if self.find_monitor()? != self.monitor {
+                        self.dispose();
+                    }
+                }
 
                 if !self.loaded {
+                    let monitor = match self.find_monitor()? {
+                        Some(monitor) => monitor,
+                        // One that was there before may come back, so that's worth waiting for.
+                        None if self.monitor.is_some() => return Err(CaptureError::Init { what: "The monitor went away.", code: None }),
+                        None => return Err(CaptureError::NoSuchScreen(self.screen)),
+                    };
+                    self.checked = Instant::now();
+                    if self.monitor.is_some_and(|old| old != monitor) {
+                        println!("monitor {} changed from {:?} to {:?}", self.screen, self.monitor.unwrap(), monitor);
+                        self.changed = true;
+                    }
+                    self.monitor = Some(monitor);
+
                     self.h_wnd_screen = GetDesktopWindow();
                     self.h_dc_screen = GetDC(self.h_wnd_screen);
-                    self.width = GetSystemMetrics(SM_CXSCREEN);
-                    self.height = GetSystemMetrics(SM_CYSCREEN);
+                    self.width = monitor.width as c_int;
+                    self.height = monitor.height as c_int;
 
                     self.h_dc = CreateCompatibleDC(self.h_dc_screen);
-                    if self.h_dc == NULL { return Err("Can't get a Windows display."); }
+                    if self.h_dc == NULL { return Err(init_error("Can't get a Windows display.")); }
 
                     self.h_bmp = CreateCompatibleBitmap(self.h_dc_screen, self.width, self.height);
-                    if self.h_bmp == NULL { return Err("Can't create a Windows buffer"); }
+                    if self.h_bmp == NULL { return Err(init_error("Can't create a Windows buffer")); }
 
                     self.loaded = true;
                 }
 
+                let monitor = self.monitor.expect("a loaded capturer has a monitor");
                 let res = SelectObject(self.h_dc, self.h_bmp);
                 if res == NULL || res == HGDI_ERROR {
-                    return Err("Can't select Windows buffer.");
+                    return Err(grab_error("Can't select Windows buffer."));
                 }
 
-                let res = BitBlt(self.h_dc, 0, 0, self.width, self.height, self.h_dc_screen, 0, 0, SRCCOPY|CAPTUREBLT);
-                if res == 0 { return Err("Failed to copy screen to Windows buffer"); }
+                let res = BitBlt(self.h_dc, 0, 0, self.width, self.height, self.h_dc_screen, monitor.x, monitor.y, SRCCOPY|CAPTUREBLT);
+                if res == 0 { return Err(grab_error("Failed to copy screen to Windows buffer")); }
 
                 // Get image info
                 let pixel_width: usize = 4; // FIXME
@@ -386,24 +821,35 @@ pub mod ffi {
                           &mut bmi as *mut BITMAPINFO as *mut c_void,
                           DIB_RGB_COLORS);
 
-                //let data = flip_rows(data, self.height as usize, self.width as usize*pixel_width);
-
                 Ok(Screenshot {
-                    data: data,
+                    data,
                     height: self.height as usize,
                     width: self.width as usize,
                     row_len: self.width as usize*pixel_width,
-                    pixel_width: pixel_width,
+                    pixel_width,
+                    origin: Origin::BottomLeft,
+                    format: PixelFormat::Bgra,
                 })
             }
         }
 
+        /// The monitor asked for, as it is now.
+        fn find_monitor(&self) -> Result<Option<Monitor>, CaptureError> {
+            Ok(super::in_order(monitors()?).get(self.screen).copied())
+        }
+
         pub fn dispose(&mut self) {
             unsafe {
                 // Release native image buffers
-                ReleaseDC(self.h_wnd_screen, self.h_dc_screen); // don't need screen anymore
-                DeleteDC(self.h_dc);
-                DeleteObject(self.h_bmp);
+                if self.h_dc_screen != NULL {
+                    ReleaseDC(self.h_wnd_screen, self.h_dc_screen); // don't need screen anymore
+                }
+                if self.h_dc != NULL {
+                    DeleteDC(self.h_dc);
+                }
+                if self.h_bmp != NULL {
+                    DeleteObject(self.h_bmp);
+                }
 
                 self.h_dc_screen = NULL;
                 self.width = 0;
@@ -415,11 +861,67 @@ pub mod ffi {
             }
         }
     }
+    impl Drop for Capturer {
+        fn drop(&mut self) {
+            self.dispose();
+        }
+    }
+
+    /// Every monitor, in whatever order Windows has them.
+    pub fn monitors() -> Result<Vec<Monitor>, CaptureError> {
+        unsafe extern "system" fn add(h_monitor: HMONITOR, _h_dc: HDC, _rect: LPRECT, data: LPARAM) -> BOOL {
+            let monitors = &mut *(data as *mut Vec<Monitor>);
+            let mut info = MONITORINFO { cbSize: size_of::<MONITORINFO>() as DWORD, ..Default::default() };
+            if GetMonitorInfo(h_monitor, &mut info) != 0 {
+                let rect = &info.rcMonitor;
+                monitors.push(Monitor {
+                    x: rect.left as i32,
+                    y: rect.top as i32,
+                    width: (rect.right - rect.left) as usize,
+                    height: (rect.bottom - rect.top) as usize,
+                    primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+                });
+            }
+            1 // keep going
+        }
+
+        let mut monitors: Vec<Monitor> = vec![];
+        unsafe {
+            if EnumDisplayMonitors(NULL, std::ptr::null(), add, &mut monitors as *mut Vec<Monitor> as LPARAM) == 0 {
+                return Err(init_error("Can't list the monitors."));
+            }
+        }
+        Ok(monitors)
+    }
+
+    /// `what` failed while setting up, with whatever `GetLastError` says about it.
+    fn init_error(what: &'static str) -> CaptureError {
+        CaptureError::Init { what, code: last_error() }
+    }
+
+    /// `what` failed while copying a frame.
+    fn grab_error(what: &'static str) -> CaptureError {
+        CaptureError::Grab { what, code: last_error() }
+    }
+
+    fn last_error() -> Option<i32> {
+        match unsafe { GetLastError() } {
+            0 => None,
+            code => Some(code as i32),
+        }
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetLastError() -> DWORD;
+    }
+
     #[link(name = "user32")]
     extern "system" {
         fn GetSystemMetrics(m: c_int) -> c_int;
         fn EnumDisplayMonitors(hdc: HDC, lprcClip: LPCRECT,
                                lpfnEnum: MONITORENUMPROC, dwData: LPARAM) -> BOOL;
+        #[link_name = "GetMonitorInfoW"]
         fn GetMonitorInfo(hMonitor: HMONITOR, lpmi: LPMONITORINFO) -> BOOL;
         fn GetDesktopWindow() -> HWND;
         fn GetDC(hWnd: HWND) -> HDC;
@@ -439,99 +941,677 @@ pub mod ffi {
         fn ReleaseDC(hWnd: HWND, hDC: HDC) -> c_int;
         fn DeleteDC(hdc: HDC) -> BOOL;
     }
+}
+
+#[cfg(target_os = "linux")]
+pub mod ffi {
+    #![allow(non_snake_case, non_upper_case_globals, dead_code, clippy::upper_case_acronyms)]
+
+    use libc::{c_char, c_int, c_uint, c_ulong, c_void};
+    use std::ptr;
+    use std::slice;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+    use std::time::Instant;
+    use crate::my_screenshot::{CaptureError, Monitor, Origin, PixelFormat, ScreenResult, Screenshot};
+
+    type Bool = c_int;
+    type XID = c_ulong;
+    type Window = XID;
+    type Drawable = XID;
+    type ShmSeg = c_ulong;
+    type Display = c_void; // Opaque
+    type Visual = c_void; // Opaque
+
+    const False: Bool = 0;
+    const True: Bool = 1;
+    const ZPixmap: c_int = 2;
+    const LSBFirst: c_int = 0;
+    const AllPlanes: c_ulong = !0;
+
+    #[repr(C)]
+    struct ImageFns {
+        create_image: *mut c_void,
+        destroy_image: *mut c_void,
+        get_pixel: *mut c_void,
+        put_pixel: *mut c_void,
+        sub_image: *mut c_void,
+        add_pixel: *mut c_void,
+    }
+
+    #[repr(C)]
+    struct XImage {
+        width: c_int,
+        height: c_int,
+        xoffset: c_int,
+        format: c_int,
+        data: *mut c_char,
+        byte_order: c_int,
+        bitmap_unit: c_int,
+        bitmap_bit_order: c_int,
+        bitmap_pad: c_int,
+        depth: c_int,
+        bytes_per_line: c_int,
+        bits_per_pixel: c_int,
+        red_mask: c_ulong,
+        green_mask: c_ulong,
+        blue_mask: c_ulong,
+        obdata: *mut c_char,
+        f: ImageFns,
+    }
+
+    #[repr(C)]
+    struct XErrorEvent {
+        type_: c_int,
+        display: *mut Display,
+        resourceid: XID,
+        serial: c_ulong,
+        error_code: u8,
+        request_code: u8,
+        minor_code: u8,
+    }
+    type XErrorHandler = Option<unsafe extern "C" fn(*mut Display, *mut XErrorEvent) -> c_int>;
+
+    #[repr(C)]
+    struct XRRMonitorInfo {
+        name: c_ulong, // Atom
+        primary: Bool,
+        automatic: Bool,
+        noutput: c_int,
+        x: c_int,
+        y: c_int,
+        width: c_int,
+        height: c_int,
+        mwidth: c_int,
+        mheight: c_int,
+        outputs: *mut c_ulong, // RROutput
+    }
+
+    #[repr(C)]
+    struct XShmSegmentInfo {
+        shmseg: ShmSeg,
+        shmid: c_int,
+        shmaddr: *mut c_char,
+        readOnly: Bool,
+    }
+
+    pub struct Capturer {
+        screen: usize,
+        loaded: bool,
+        display: *mut Display,
+        root: Window,
+        width: c_int,
+        height: c_int,
+        shm_image: *mut XImage,
+        shm_info: XShmSegmentInfo,
+        /// Whether to try XShm at all. Without it every frame goes through XGetImage.
+        use_shm: bool,
+        /// The monitor as it was when last loaded. Kept across `dispose`.
+        monitor: Option<Monitor>,
+        /// When the monitors were last listed.
+        checked: Instant,
+        changed: bool,
+    }
+
+    impl Capturer {
+        pub fn new(_screen: usize) -> Self {
+            Self {
+                screen: _screen,
+                loaded: false,
+                display: ptr::null_mut(),
+                root: 0,
+                width: 0,
+                height: 0,
+                shm_image: ptr::null_mut(),
+                shm_info: XShmSegmentInfo {
+                    shmseg: 0,
+                    shmid: -1,
+                    shmaddr: ptr::null_mut(),
+                    readOnly: False,
+                },
+                use_shm: true,
+                monitor: None,
+                checked: Instant::now(),
+                changed: false,
+            }
+        }
+
+        /// True once after the monitor came back different from how it was loaded.
+        pub fn display_changed(&mut self) -> bool {
+            std::mem::replace(&mut self.changed, false)
+        }
+
+        pub fn get_screenshot(&mut self) -> ScreenResult {
+            unsafe {
+                // Monitors are looked up again every so often: RandR moves and resizes
+                // them under a live connection, like when one is plugged in.
+                if self.loaded && self.checked.elapsed() >= super::MONITOR_CHECK {
+                    self.checked = Instant::now();
+                    if self.find_monitor() != self.monitor {
+                        self.dispose();
+                    }
+                }
+
+                if !self.loaded {
+                    // NULL means "whatever $DISPLAY says", which is also how Xvfb is picked up.
+                    self.display = XOpenDisplay(ptr::null());
+                    if self.display.is_null() { return Err(CaptureError::Init { what: "Can't open X display.", code: None }); }
+
+                    let screen = XDefaultScreen(self.display);
+                    self.root = XRootWindow(self.display, screen);
+                    let monitor = match self.find_monitor() {
+                        Some(monitor) => monitor,
+                        None => {
+                            XCloseDisplay(self.display);
+                            self.display = ptr::null_mut();
+                            return Err(self.missing_monitor());
+                        }
+                    };
+                    self.checked = Instant::now();
+                    if self.monitor.is_some_and(|old| old != monitor) {
+                        println!("monitor {} changed from {:?} to {:?}", self.screen, self.monitor.unwrap(), monitor);
+                        self.changed = true;
+                    }
+                    self.monitor = Some(monitor);
+                    self.width = monitor.width as c_int;
+                    self.height = monitor.height as c_int;
+
+                    // XShm is an optimisation only. Remote displays and sandboxes without
+                    // SysV shared memory end up on the XGetImage path below.
+                    if self.use_shm && XShmQueryExtension(self.display) == True {
+                        self.init_shm(screen);
+                    }
+
+                    self.loaded = true;
+                }
+
+                let (x, y) = self.monitor.map_or((0, 0), |monitor| monitor.origin());
+                // A monitor that shrank since the last check makes the copy run off the
+                // root window, which X answers with BadMatch.
+                let image = if !self.shm_image.is_null() {
+                    let (copied, error) = trap_errors(self.display, || {
+                        XShmGetImage(self.display, self.root, self.shm_image, x, y, AllPlanes)
+                    });
+                    if let Some(code) = error { return Err(resized_error(code)); }
+                    if copied == False {
+                        return Err(CaptureError::Grab { what: "Failed to copy screen to shared memory", code: None });
+                    }
+                    self.shm_image
+                } else {
+                    let (image, error) = trap_errors(self.display, || {
+                        XGetImage(self.display, self.root, x, y, self.width as c_uint, self.height as c_uint,
+                                  AllPlanes, ZPixmap)
+                    });
+                    if let Some(code) = error {
+                        if !image.is_null() { XDestroyImage(image); }
+                        return Err(resized_error(code));
+                    }
+                    if image.is_null() { return Err(CaptureError::Grab { what: "Failed to copy screen to X image", code: None }); }
+                    image
+                };
+
+                let res = to_screenshot(&*image);
+                if image != self.shm_image {
+                    XDestroyImage(image);
+                }
+                res
+            }
+        }
+
+        /// The monitor asked for, as it is now.
+        unsafe fn find_monitor(&self) -> Option<Monitor> {
+            super::in_order(monitors_on(self.display, self.root)).get(self.screen).copied()
+        }
+
+        /// Why there's no monitor to load. One that was there before may come back,
+        /// like after a hotplug or a mode switch, so that's worth waiting for.
+        fn missing_monitor(&self) -> CaptureError {
+            match self.monitor {
+                Some(_) => CaptureError::Init { what: "The monitor went away.", code: None },
+                None => CaptureError::NoSuchScreen(self.screen),
+            }
+        }
+
+        /// Sets up a shared memory XImage. Leaves `shm_image` null if anything along the
+        /// way fails, so callers fall back to XGetImage.
+        unsafe fn init_shm(&mut self, screen: c_int) {
+            let image = XShmCreateImage(self.display, XDefaultVisual(self.display, screen),
+                                        XDefaultDepth(self.display, screen) as c_uint, ZPixmap,
+                                        ptr::null_mut(), &mut self.shm_info,
+                                        self.width as c_uint, self.height as c_uint);
+            if image.is_null() { return; }
+
+            let size = ((*image).bytes_per_line * (*image).height) as usize;
+            self.shm_info.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if self.shm_info.shmid < 0 {
+                XDestroyImage(image);
+                return;
+            }
+
+            let addr = libc::shmat(self.shm_info.shmid, ptr::null(), 0);
+            if addr as isize == -1 {
+                libc::shmctl(self.shm_info.shmid, libc::IPC_RMID, ptr::null_mut());
+                self.shm_info.shmid = -1;
+                XDestroyImage(image);
+                return;
+            }
+            self.shm_info.shmaddr = addr as *mut c_char;
+            self.shm_info.readOnly = False;
+            (*image).data = self.shm_info.shmaddr;
+
+            // The X server reports a failed attach asynchronously, e.g. when it runs in
+            // another IPC namespace.
+            let (attached, error) = trap_errors(self.display, || XShmAttach(self.display, &mut self.shm_info));
+
+            // Mark the segment for removal now; it lives until the last detach.
+            libc::shmctl(self.shm_info.shmid, libc::IPC_RMID, ptr::null_mut());
+
+            if attached == False || error.is_some() {
+                (*image).data = ptr::null_mut();
+                XDestroyImage(image);
+                libc::shmdt(self.shm_info.shmaddr as *const c_void);
+                self.shm_info.shmaddr = ptr::null_mut();
+                self.shm_info.shmid = -1;
+                return;
+            }
+
+            self.shm_image = image;
+        }
+
+        pub fn dispose(&mut self) {
+            unsafe {
+                if !self.shm_image.is_null() {
+                    XShmDetach(self.display, &mut self.shm_info);
+                    // The data belongs to the shm segment, not to Xlib.
+                    (*self.shm_image).data = ptr::null_mut();
+                    XDestroyImage(self.shm_image);
+                    libc::shmdt(self.shm_info.shmaddr as *const c_void);
+                }
+                if !self.display.is_null() {
+                    XCloseDisplay(self.display);
+                }
 
-    /// Reorder rows in bitmap, last to first.
-    /// TODO rewrite functionally
-    fn flip_rows(data: Vec<u8>, height: usize, row_len: usize) -> Vec<u8> {
-        let start = Instant::now();
-        let mut new_data = Vec::with_capacity(data.len());
-        unsafe {new_data.set_len(data.len())};
-        for row_i in 0..height {
-            for byte_i in 0..row_len {
-                let old_idx = (height-row_i-1)*row_len + byte_i;
-                let new_idx = row_i*row_len + byte_i;
-                new_data[new_idx] = data[old_idx];
+                self.display = ptr::null_mut();
+                self.root = 0;
+                self.width = 0;
+                self.height = 0;
+                self.shm_image = ptr::null_mut();
+                self.shm_info.shmid = -1;
+                self.shm_info.shmaddr = ptr::null_mut();
+                self.loaded = false;
             }
         }
-        println!("flip_rows took: {} ms", start.elapsed().as_millis());
-        new_data
     }
 
-    // TODO Support multiple screens
-    // This may never happen, given the horrific quality of Win32 APIs
-    /*pub fn get_screenshot(_screen: usize) -> ScreenResult {
+    impl Drop for Capturer {
+        fn drop(&mut self) {
+            self.dispose();
+        }
+    }
+
+    /// Every monitor, in whatever order the server has them.
+    pub fn monitors() -> Result<Vec<Monitor>, CaptureError> {
         unsafe {
-            // Enumerate monitors, getting a handle and DC for requested monitor.
-            // loljk, because doing that on Windows is worse than death
-            let h_wnd_screen = GetDesktopWindow();
-            let h_dc_screen = GetDC(h_wnd_screen);
-            let width = GetSystemMetrics(SM_CXSCREEN);
-            let height = GetSystemMetrics(SM_CYSCREEN);
-
-            // Create a Windows Bitmap, and copy the bits into it
-            let h_dc = CreateCompatibleDC(h_dc_screen);
-            if h_dc == NULL { return Err("Can't get a Windows display.");}
-
-            let h_bmp = CreateCompatibleBitmap(h_dc_screen, width, height);
-            if h_bmp == NULL { return Err("Can't create a Windows buffer");}
-
-            let res = SelectObject(h_dc, h_bmp);
-            if res == NULL || res == HGDI_ERROR {
-                return Err("Can't select Windows buffer.");
+            let display = XOpenDisplay(ptr::null());
+            if display.is_null() { return Err(CaptureError::Init { what: "Can't open X display.", code: None }); }
+            let monitors = monitors_on(display, XRootWindow(display, XDefaultScreen(display)));
+            XCloseDisplay(display);
+            Ok(monitors)
+        }
+    }
+
+    /// The active RandR monitors on `root`, or all of `root` as one monitor when
+    /// the server has no RandR 1.5 (Xvfb, older servers, some remote displays).
+    unsafe fn monitors_on(display: *mut Display, root: Window) -> Vec<Monitor> {
+        let (mut event_base, mut error_base) = (0, 0);
+        let (mut major, mut minor) = (0, 0);
+        let mut monitors = vec![];
+        if XRRQueryExtension(display, &mut event_base, &mut error_base) == True
+            && XRRQueryVersion(display, &mut major, &mut minor) != 0
+            && (major, minor) >= (1, 5)
+        {
+            let mut count = 0;
+            let infos = XRRGetMonitors(display, root, True, &mut count);
+            if !infos.is_null() {
+                for info in slice::from_raw_parts(infos, count.max(0) as usize) {
+                    monitors.push(Monitor {
+                        x: info.x,
+                        y: info.y,
+                        width: info.width.max(0) as usize,
+                        height: info.height.max(0) as usize,
+                        primary: info.primary == True,
+                    });
+                }
+                XRRFreeMonitors(infos);
             }
+        }
 
-            let res = BitBlt(h_dc, 0, 0, width, height, h_dc_screen, 0, 0, SRCCOPY|CAPTUREBLT);
-            if res == 0 { return Err("Failed to copy screen to Windows buffer");}
-
-            // Get image info
-            let pixel_width: usize = 4; // FIXME
-            let mut bmi = BITMAPINFO {
-                bmiHeader: BITMAPINFOHEADER {
-                    biSize: size_of::<BITMAPINFOHEADER>() as DWORD,
-                    biWidth: width as LONG,
-                    biHeight: height as LONG,
-                    biPlanes: 1,
-                    biBitCount: 8*pixel_width as WORD,
-                    biCompression: BI_RGB,
-                    biSizeImage: (width * height * pixel_width as c_int) as DWORD,
-                    biXPelsPerMeter: 0,
-                    biYPelsPerMeter: 0,
-                    biClrUsed: 0,
-                    biClrImportant: 0,
-                },
-                bmiColors: [RGBQUAD {
-                    rgbBlue: 0,
-                    rgbGreen: 0,
-                    rgbRed: 0,
-                    rgbReserved: 0
-                }],
-            };
+        if monitors.is_empty() {
+            let (width, height) = root_size(display, root);
+            monitors.push(Monitor { x: 0, y: 0, width: width as usize, height: height as usize, primary: true });
+        }
+        monitors
+    }
+
+    /// Current width and height of `root`.
+    unsafe fn root_size(display: *mut Display, root: Window) -> (c_int, c_int) {
+        let (mut root_return, mut x, mut y) = (0, 0, 0);
+        let (mut width, mut height, mut border, mut depth) = (0, 0, 0, 0);
+        XGetGeometry(display, root, &mut root_return, &mut x, &mut y, &mut width, &mut height, &mut border, &mut depth);
+        (width as c_int, height as c_int)
+    }
+
+    /// An X error while copying a frame, most likely because the monitor changed size
+    /// since the capturer was set up. Setting it up again sorts that out.
+    fn resized_error(code: u8) -> CaptureError {
+        CaptureError::Init { what: "X error while copying the screen, it may have changed size.", code: Some(code as i32) }
+    }
+
+    /// Xlib has one error handler for the whole process, and the default one exits it.
+    /// Capturers on different threads take turns swapping it out behind this lock.
+    static TRAP: Mutex<()> = Mutex::new(());
+    /// The display errors are being trapped for.
+    static TRAPPED_DISPLAY: AtomicPtr<Display> = AtomicPtr::new(ptr::null_mut());
+    /// Code of the first error trapped on it, 0 for none yet.
+    static TRAPPED_ERROR: AtomicU8 = AtomicU8::new(0);
+
+    /// Runs `f` and waits for the server to have handled it, with any X error it
+    /// raises on `display` trapped instead of exiting the process. Returns what `f`
+    /// returned and the code of the first error, if there was one.
+    unsafe fn trap_errors<T, F: FnOnce() -> T>(display: *mut Display, f: F) -> (T, Option<u8>) {
+        let _turn = TRAP.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        TRAPPED_DISPLAY.store(display, Ordering::SeqCst);
+        TRAPPED_ERROR.store(0, Ordering::SeqCst);
+        let old_handler = XSetErrorHandler(Some(trap_error));
+        let result = f();
+        XSync(display, False);
+        XSetErrorHandler(old_handler);
+        TRAPPED_DISPLAY.store(ptr::null_mut(), Ordering::SeqCst);
+        match TRAPPED_ERROR.swap(0, Ordering::SeqCst) {
+            0 => (result, None),
+            code => (result, Some(code)),
+        }
+    }
+
+    unsafe extern "C" fn trap_error(display: *mut Display, event: *mut XErrorEvent) -> c_int {
+        // Another thread's display can report an error while the handler is swapped
+        // in. It isn't the trapping thread's to see, and exiting would be worse.
+        if display == TRAPPED_DISPLAY.load(Ordering::SeqCst) {
+            let _ = TRAPPED_ERROR.compare_exchange(0, (*event).error_code, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        0
+    }
+
+    /// Copies a ZPixmap XImage into a `Screenshot`, dropping any row padding.
+    unsafe fn to_screenshot(image: &XImage) -> ScreenResult {
+        // 24 and 32 bit visuals both use 4 byte pixels; on a little endian server that
+        // is B, G, R, X, which is the same order GDI hands us on Windows.
+        if image.bits_per_pixel != 32 || image.byte_order != LSBFirst {
+            return Err(CaptureError::Unsupported("Unsupported X visual, expected 32 bits per pixel little endian."));
+        }
+
+        let pixel_width: usize = 4;
+        let width = image.width as usize;
+        let height = image.height as usize;
+        let row_len = width * pixel_width;
+        let src_row_len = image.bytes_per_line as usize;
+        let src = slice::from_raw_parts(image.data as *const u8, src_row_len * height);
+
+        let mut data: Vec<u8> = Vec::with_capacity(row_len * height);
+        for row in src.chunks(src_row_len) {
+            data.extend_from_slice(&row[..row_len]);
+        }
 
-            // Create a Vec for image
-            let size: usize = (width*height) as usize * pixel_width;
-            let mut data: Vec<u8> = Vec::with_capacity(size);
-            data.set_len(size);
-
-            // copy bits into Vec
-            GetDIBits(h_dc, h_bmp, 0, height as DWORD,
-                      &mut data[0] as *mut u8 as *mut c_void,
-                      &mut bmi as *mut BITMAPINFO as *mut c_void,
-                      DIB_RGB_COLORS);
-
-            // Release native image buffers
-            ReleaseDC(h_wnd_screen, h_dc_screen); // don't need screen anymore
-            DeleteDC(h_dc);
-            DeleteObject(h_bmp);
-
-            let data = flip_rows(data, height as usize, width as usize*pixel_width);
-
-            Ok(Screenshot {
-                data: data,
-                height: height as usize,
-                width: width as usize,
-                row_len: width as usize*pixel_width,
-                pixel_width: pixel_width,
-            })
-        }
-    }*/
+        Ok(Screenshot {
+            data,
+            height,
+            width,
+            row_len,
+            pixel_width,
+            origin: Origin::TopLeft,
+            format: PixelFormat::Bgra,
+        })
+    }
+
+    #[link(name = "X11")]
+    extern "C" {
+        fn XOpenDisplay(display_name: *const c_char) -> *mut Display;
+        fn XCloseDisplay(display: *mut Display) -> c_int;
+        fn XDefaultScreen(display: *mut Display) -> c_int;
+        fn XRootWindow(display: *mut Display, screen_number: c_int) -> Window;
+        fn XGetGeometry(display: *mut Display, d: Drawable, root_return: *mut Window,
+                        x_return: *mut c_int, y_return: *mut c_int,
+                        width_return: *mut c_uint, height_return: *mut c_uint,
+                        border_width_return: *mut c_uint, depth_return: *mut c_uint) -> c_int;
+        fn XDefaultVisual(display: *mut Display, screen_number: c_int) -> *mut Visual;
+        fn XDefaultDepth(display: *mut Display, screen_number: c_int) -> c_int;
+        fn XGetImage(display: *mut Display, d: Drawable, x: c_int, y: c_int,
+                     width: c_uint, height: c_uint, plane_mask: c_ulong, format: c_int) -> *mut XImage;
+        fn XDestroyImage(image: *mut XImage) -> c_int;
+        fn XSync(display: *mut Display, discard: Bool) -> c_int;
+        fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+    }
+
+    #[link(name = "Xrandr")]
+    extern "C" {
+        fn XRRQueryExtension(display: *mut Display, event_base: *mut c_int, error_base: *mut c_int) -> Bool;
+        fn XRRQueryVersion(display: *mut Display, major: *mut c_int, minor: *mut c_int) -> c_int;
+        fn XRRGetMonitors(display: *mut Display, window: Window, get_active: Bool, nmonitors: *mut c_int) -> *mut XRRMonitorInfo;
+        fn XRRFreeMonitors(monitors: *mut XRRMonitorInfo);
+    }
+
+    #[link(name = "Xext")]
+    extern "C" {
+        fn XShmQueryExtension(display: *mut Display) -> Bool;
+        fn XShmCreateImage(display: *mut Display, visual: *mut Visual, depth: c_uint, format: c_int,
+                           data: *mut c_char, shminfo: *mut XShmSegmentInfo,
+                           width: c_uint, height: c_uint) -> *mut XImage;
+        fn XShmAttach(display: *mut Display, shminfo: *mut XShmSegmentInfo) -> Bool;
+        fn XShmDetach(display: *mut Display, shminfo: *mut XShmSegmentInfo) -> Bool;
+        fn XShmGetImage(display: *mut Display, d: Drawable, image: *mut XImage,
+                        x: c_int, y: c_int, plane_mask: c_ulong) -> Bool;
+    }
+
+    /// These need an X server, so they're ignored by default. On a machine without
+    /// a desktop run them under Xvfb: `xvfb-run cargo test -- --ignored`.
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[link(name = "X11")]
+        extern "C" {
+            fn XCreateSimpleWindow(display: *mut Display, parent: Window, x: c_int, y: c_int,
+                                   width: c_uint, height: c_uint, border_width: c_uint,
+                                   border: c_ulong, background: c_ulong) -> Window;
+            fn XMapRaised(display: *mut Display, w: Window) -> c_int;
+            fn XDestroyWindow(display: *mut Display, w: Window) -> c_int;
+        }
+
+        /// Captures screen 0 with a window of a known colour in its top left corner.
+        unsafe fn capture_known_colour(use_shm: bool) {
+            let display = XOpenDisplay(ptr::null());
+            assert!(!display.is_null(), "can't open $DISPLAY");
+            let root = XRootWindow(display, XDefaultScreen(display));
+            let monitor = crate::my_screenshot::in_order(monitors_on(display, root))[0];
+
+            // On a 24 bit TrueColor visual, which is what Xvfb has by default,
+            // a pixel value is just 0xRRGGBB.
+            let window = XCreateSimpleWindow(display, root, monitor.x, monitor.y, 64, 64, 0, 0, 0x20c050);
+            XMapRaised(display, window);
+            XSync(display, False);
+
+            let mut capturer = Capturer::new(0);
+            capturer.use_shm = use_shm;
+            let screenshot = capturer.get_screenshot().expect("capturing screen 0");
+            if !use_shm {
+                assert!(capturer.shm_image.is_null());
+            }
+            assert_eq!(screenshot.width(), monitor.width);
+            assert_eq!(screenshot.height(), monitor.height);
+            assert_eq!(screenshot.pixel(32, 32).map(|pixel| pixel.rgb()), Some((0x20, 0xc0, 0x50)));
+
+            XDestroyWindow(display, window);
+            XCloseDisplay(display);
+        }
+
+        #[test]
+        #[ignore = "needs an X server, run under xvfb-run with --ignored"]
+        fn captures_a_window_of_a_known_colour() {
+            unsafe { capture_known_colour(true) }
+        }
+
+        #[test]
+        #[ignore = "needs an X server, run under xvfb-run with --ignored"]
+        fn captures_through_xgetimage_without_shm() {
+            unsafe { capture_known_colour(false) }
+        }
+
+        #[test]
+        #[ignore = "needs an X server, run under xvfb-run with --ignored"]
+        fn traps_a_copy_off_the_edge_of_the_screen() {
+            unsafe {
+                let display = XOpenDisplay(ptr::null());
+                assert!(!display.is_null(), "can't open $DISPLAY");
+                let root = XRootWindow(display, XDefaultScreen(display));
+                let (width, height) = root_size(display, root);
+
+                // What a capturer set up for a bigger screen than there now is would ask for.
+                let (image, error) = trap_errors(display, || {
+                    XGetImage(display, root, 0, 0, width as c_uint + 16, height as c_uint, AllPlanes, ZPixmap)
+                });
+                assert!(image.is_null());
+                let err = resized_error(error.expect("BadMatch"));
+                assert!(err.is_retryable() && err.needs_reinit());
+                XCloseDisplay(display);
+            }
+        }
+
+        #[test]
+        #[ignore = "needs an X server, run under xvfb-run with --ignored"]
+        fn waits_for_a_monitor_that_went_away() {
+            let mut capturer = Capturer::new(99);
+            assert_eq!(capturer.get_screenshot().err(), Some(CaptureError::NoSuchScreen(99)));
+
+            capturer.monitor = Some(Monitor { x: 0, y: 0, width: 640, height: 480, primary: false });
+            let err = capturer.get_screenshot().err().expect("no monitor 99");
+            assert!(err.is_retryable() && err.needs_reinit(), "{:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 frame the way GDI hands it over: bottom row first, BGRA, here with
+    /// 4 bytes of padding after each row. Pixel (x, y) has red 10 * x + y.
+    fn bottom_up() -> Screenshot {
+        let (width, height, pixel_width, row_len) = (3, 2, 4, 16);
+        let mut data = vec![0xee; row_len * height];
+        for y in 0..height {
+            let stored = height - 1 - y;
+            for x in 0..width {
+                let at = stored * row_len + x * pixel_width;
+                data[at..at + 4].copy_from_slice(&[7, 5, (10 * x + y) as u8, 255]);
+            }
+        }
+        Screenshot { data, height, width, row_len, pixel_width, origin: Origin::BottomLeft, format: PixelFormat::Bgra }
+    }
+
+    #[test]
+    fn reads_pixels_up_to_the_last_row_and_column() {
+        let screenshot = bottom_up();
+        assert_eq!(screenshot.pixel(0, 0), Some(Pixel { a: 255, r: 0, g: 5, b: 7 }));
+        assert_eq!(screenshot.pixel(2, 0).map(|pixel| pixel.r), Some(20));
+        assert_eq!(screenshot.pixel(0, 1).map(|pixel| pixel.r), Some(1));
+        assert_eq!(screenshot.pixel(2, 1).map(|pixel| pixel.r), Some(21));
+        assert_eq!(screenshot.pixel(3, 0), None);
+        assert_eq!(screenshot.pixel(0, 2), None);
+        assert_eq!(screenshot.pixel(3, 2), None);
+    }
+
+    #[test]
+    fn rows_run_top_to_bottom_without_padding() {
+        let screenshot = bottom_up();
+        let rows: Vec<Vec<u8>> = screenshot.rows().map(|row| row.map(|pixel| pixel.r).collect()).collect();
+        assert_eq!(rows, vec![vec![0, 10, 20], vec![1, 11, 21]]);
+        assert!(screenshot.row(2).is_none());
+    }
+
+    #[test]
+    fn reads_top_down_rgba_too() {
+        let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([(10 * x + y) as u8, 5, 7, 255]));
+        let screenshot = Screenshot::from(&image);
+        assert_eq!(screenshot.origin(), Origin::TopLeft);
+        assert_eq!(screenshot.format(), PixelFormat::Rgba);
+        for (x, y) in [(0, 0), (2, 0), (0, 1), (2, 1)] {
+            assert_eq!(screenshot.pixel(x, y), bottom_up().pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn regions_must_fit_inside() {
+        let screenshot = bottom_up();
+        assert!(screenshot.region(Region::new(0, 0, 3, 2)).is_some());
+        assert!(screenshot.region(Region::new(1, 0, 3, 2)).is_none());
+        assert!(screenshot.region(Region::new(0, 1, 1, 2)).is_none());
+
+        let view = screenshot.region(Region::new(1, 1, 2, 1)).unwrap();
+        assert_eq!(view.pixel(0, 0).map(|pixel| pixel.r), Some(11));
+        assert_eq!(view.pixel(1, 0).map(|pixel| pixel.r), Some(21));
+        assert_eq!(view.pixel(2, 0), None);
+        assert_eq!(view.pixel(0, 1), None);
+        let pixels: Vec<(usize, usize, u8)> = view.enumerate_pixels().map(|(x, y, pixel)| (x, y, pixel.r)).collect();
+        assert_eq!(pixels, vec![(0, 0, 11), (1, 0, 21)]);
+
+        let inner = screenshot.view().region(Region::new(2, 0, 1, 2)).unwrap();
+        assert_eq!(inner.bounds(), Region::new(2, 0, 1, 2));
+        assert!(screenshot.view().region(Region::new(2, 0, 2, 2)).is_none());
+    }
+
+    #[test]
+    fn converts_to_an_rgba_image_and_back() {
+        let image = RgbaImage::from(&bottom_up());
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.get_pixel(2, 1), &Rgba([21, 5, 7, 255]));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 5, 7, 255]));
+
+        let pixels = |screenshot: &Screenshot| -> Vec<Pixel> {
+            screenshot.view().enumerate_pixels().map(|(_, _, pixel)| pixel).collect()
+        };
+        assert_eq!(pixels(&Screenshot::from(&image)), pixels(&bottom_up()));
+    }
+
+    #[test]
+    fn saves_a_png_with_the_frame_info() {
+        let path = std::env::temp_dir().join(format!("cookie_monster_frame_{}.png", std::process::id()));
+        let info = FrameInfo::now(Some(1));
+        bottom_up().save_png(&path, &info).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let text: Vec<(String, String)> = reader.info().uncompressed_latin1_text.iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+            .collect();
+        let reloaded = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(text.contains(&("Creation Time".to_string(), info.captured_at.to_rfc2822())));
+        assert!(text.contains(&("Screen".to_string(), "1".to_string())));
+        assert!(text.contains(&("Software".to_string(), "cookie_monster".to_string())));
+        // The alpha channel isn't saved, so it comes back opaque.
+        assert_eq!(reloaded, RgbaImage::from(&bottom_up()));
+    }
+
+    #[test]
+    fn numbers_the_primary_monitor_first_then_left_to_right() {
+        let monitor = |x: i32, y: i32, primary: bool| Monitor { x, y, width: 1920, height: 1080, primary };
+        let right = monitor(1920, 0, false);
+        let primary = monitor(0, 0, true);
+        let left_top = monitor(-1920, -1080, false);
+        let left = monitor(-1920, 0, false);
+
+        assert_eq!(in_order(vec![right, primary, left, left_top]), vec![primary, left_top, left, right]);
+        assert_eq!(in_order(vec![]), vec![]);
+    }
 }
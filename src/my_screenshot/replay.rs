@@ -0,0 +1,178 @@
+//! Replays saved frames as if they came from a display, so detections can be
+//! debugged offline.
+//!
+//! A replay source is either a single PNG, which is handed out over and over,
+//! or a directory of PNGs. Frames in a directory are ordered by the number in
+//! their file name, which is read as a capture timestamp in milliseconds
+//! (e.g. `1681234567890.png`). Files without a numeric name are played after
+//! the timestamped ones, in name order, without any pacing between them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::my_screenshot::{CaptureError, CaptureSource, ScreenResult, Screenshot};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// Wait between frames as long as passed between their captures.
+    RealTime,
+    /// Hand out the next frame as soon as it's asked for.
+    AsFastAsPossible,
+}
+
+struct Frame {
+    path: PathBuf,
+    timestamp: Option<u64>,
+}
+
+pub struct ReplaySource {
+    frames: Vec<Frame>,
+    next: usize,
+    repeat: bool,
+    pacing: Pacing,
+    started: Option<(Instant, u64)>,
+    /// Size of the last frame handed out, and whether it differed from the one before.
+    size: Option<(usize, usize)>,
+    changed: bool,
+}
+
+impl ReplaySource {
+    /// Replays a single PNG, or every PNG in a directory.
+    pub fn open<P: AsRef<Path>>(path: P, pacing: Pacing) -> Result<Self, CaptureError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Self::directory(path, pacing)
+        } else {
+            Ok(Self::file(path))
+        }
+    }
+
+    /// Hands out the same frame on every call.
+    pub fn file<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            frames: vec![Frame { path: path.as_ref().to_path_buf(), timestamp: None }],
+            next: 0,
+            repeat: true,
+            pacing: Pacing::AsFastAsPossible,
+            started: None,
+            size: None,
+            changed: false,
+        }
+    }
+
+    /// Plays every PNG in `dir` once, oldest first.
+    pub fn directory<P: AsRef<Path>>(dir: P, pacing: Pacing) -> Result<Self, CaptureError> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|err| CaptureError::Replay { path: dir.to_path_buf(), reason: err.to_string() })?;
+
+        let mut frames: Vec<Frame> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+            .map(|path| {
+                let timestamp = path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok());
+                Frame { path, timestamp }
+            })
+            .collect();
+        if frames.is_empty() {
+            return Err(CaptureError::Replay { path: dir.to_path_buf(), reason: "no PNG frames in it".to_string() });
+        }
+        frames.sort_by(|a, b| match (a.timestamp, b.timestamp) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.path.cmp(&b.path),
+        });
+
+        Ok(Self {
+            frames,
+            next: 0,
+            repeat: false,
+            pacing,
+            started: None,
+            size: None,
+            changed: false,
+        })
+    }
+
+    /// Sleeps until `timestamp` is due, measured from the first timestamped frame.
+    fn wait_for(&mut self, timestamp: u64) {
+        match self.started {
+            None => self.started = Some((Instant::now(), timestamp)),
+            Some((start, first)) => {
+                let due = start + Duration::from_millis(timestamp.saturating_sub(first));
+                let now = Instant::now();
+                if due > now {
+                    sleep(due - now);
+                }
+            }
+        }
+    }
+}
+
+impl CaptureSource for ReplaySource {
+    fn get_screenshot(&mut self) -> ScreenResult {
+        if self.next >= self.frames.len() {
+            return Err(CaptureError::ReplayEnded);
+        }
+        let idx = self.next;
+        if !self.repeat {
+            self.next += 1;
+        }
+
+        if let (Pacing::RealTime, Some(timestamp)) = (self.pacing, self.frames[idx].timestamp) {
+            self.wait_for(timestamp);
+        }
+
+        let path = &self.frames[idx].path;
+        let image = image::open(path)
+            .map_err(|err| CaptureError::Replay { path: path.clone(), reason: err.to_string() })?
+            .to_rgba8();
+
+        let size = (image.width() as usize, image.height() as usize);
+        if self.size.is_some_and(|old| old != size) {
+            self.changed = true;
+        }
+        self.size = Some(size);
+        Ok(Screenshot::from(&image))
+    }
+
+    /// A frame of a different size stands in for a resolution change.
+    fn display_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn plays_timestamps_in_order_then_the_rest_by_name() {
+        let dir = std::env::temp_dir().join(format!("cookie_monster_replay_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Each frame's red channel says where it should come in the replay.
+        for (name, place) in [("1000.png", 3), ("b.png", 5), ("30.png", 1), ("a.png", 4), ("200.png", 2)] {
+            RgbaImage::from_pixel(2, 2, Rgba([place, 0, 0, 255])).save(dir.join(name)).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a frame").unwrap();
+
+        let mut source = ReplaySource::open(&dir, Pacing::AsFastAsPossible).unwrap();
+        let mut played = vec![];
+        loop {
+            match source.get_screenshot() {
+                Ok(screenshot) => played.push(screenshot.pixel(0, 0).unwrap().r),
+                Err(CaptureError::ReplayEnded) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(played, vec![1, 2, 3, 4, 5]);
+    }
+}
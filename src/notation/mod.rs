@@ -0,0 +1,314 @@
+//! Cookie Clicker's number notation.
+//!
+//! The game writes anything under a million as a plain integer with thousands
+//! separators ("1,234,567" only shows up in a few places). From a million up it
+//! divides by powers of a thousand and keeps three decimals, naming the power
+//! either in words ("12.345 million") or with a short suffix ("12.345M"). With
+//! short numbers turned off, huge values come out in JavaScript's scientific
+//! notation ("1.23e+45").
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+use std::str::FromStr;
+
+/// The names of 10^6, 10^9, ... in the order the game uses them.
+fn long_names() -> Vec<String> {
+    let mut names: Vec<String> = ["million", "billion", "trillion", "quadrillion", "quintillion",
+        "sextillion", "septillion", "octillion", "nonillion"]
+        .iter().map(|name| name.to_string()).collect();
+    let prefixes = ["", "un", "duo", "tre", "quattuor", "quin", "sex", "septen", "octo", "novem"];
+    let suffixes = ["decillion", "vigintillion", "trigintillion", "quadragintillion", "quinquagintillion",
+        "sexagintillion", "septuagintillion", "octogintillion", "nonagintillion"];
+    for suffix in suffixes.iter() {
+        for prefix in prefixes.iter() {
+            names.push(format!("{}{}", prefix, suffix));
+        }
+    }
+    names
+}
+
+/// Short suffixes for 10^6, 10^9, ... From decillion on the game puts a space before them.
+fn short_names() -> Vec<String> {
+    let mut names: Vec<String> = ["M", "B", "T", "Qa", "Qi", "Sx", "Sp", "Oc", "No"]
+        .iter().map(|name| name.to_string()).collect();
+    let prefixes = ["", "Un", "Do", "Tr", "Qa", "Qi", "Sx", "Sp", "Oc", "No"];
+    let suffixes = ["D", "V", "T", "Qa", "Qi", "Sx", "Sp", "O", "N"];
+    for suffix in suffixes.iter() {
+        for prefix in prefixes.iter() {
+            names.push(format!(" {}{}", prefix, suffix));
+        }
+    }
+    names
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    /// "12.345 million"
+    Long,
+    /// "12.345M"
+    Short,
+    /// "1.23e+45", and plain integers below 10^21.
+    Scientific,
+}
+
+/// A number of cookies. Fractions are kept, the game tracks them too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct CookieAmount(pub f64);
+
+impl CookieAmount {
+    pub const ZERO: CookieAmount = CookieAmount(0.0);
+
+    #[inline]
+    pub fn value(self) -> f64 { self.0 }
+
+    /// Writes the amount the way the game displays it.
+    pub fn format(self, notation: Notation) -> String {
+        let negative = self.0 < 0.0;
+        let val = self.0.abs().floor();
+        let output = if !val.is_finite() {
+            "Infinity".to_string()
+        } else if val < 1_000_000.0 {
+            group_thousands(&format!("{}", val))
+        } else {
+            match notation {
+                Notation::Long => every_third_power(val, &long_names(), " "),
+                Notation::Short => every_third_power(val, &short_names(), ""),
+                Notation::Scientific if val >= 1e21 => js_precision_3(val),
+                Notation::Scientific => group_thousands(&format!("{}", val)),
+            }
+        };
+        if negative && output != "0" { format!("-{}", output) } else { output }
+    }
+
+    /// `self / rate`, e.g. how long it takes to bank this much at `rate` per second.
+    pub fn ratio(self, rate: CookieAmount) -> f64 {
+        self.0 / rate.0
+    }
+
+    pub fn max(self, other: CookieAmount) -> CookieAmount {
+        CookieAmount(self.0.max(other.0))
+    }
+
+    pub fn min(self, other: CookieAmount) -> CookieAmount {
+        CookieAmount(self.0.min(other.0))
+    }
+}
+
+/// The game's `formatEveryThirdPower`: at most three decimals, trailing zeros dropped.
+fn every_third_power(mut val: f64, names: &[String], separator: &str) -> String {
+    let mut base = 0;
+    val /= 1000.0;
+    while val.round() >= 1000.0 {
+        val /= 1000.0;
+        base += 1;
+    }
+    // `base` 0 would be "thousand", which only happens for values the caller doesn't pass.
+    match names.get(base.max(1) - 1) {
+        Some(name) => format!("{}{}{}", trim_decimals((val * 1000.0).round() / 1000.0), separator, name),
+        None => "Infinity".to_string(),
+    }
+}
+
+/// JavaScript's `toPrecision(3)` for large numbers, e.g. "1.20e+45".
+fn js_precision_3(val: f64) -> String {
+    let formatted = format!("{:.2e}", val);
+    match formatted.split_once('e') {
+        Some((mantissa, exponent)) if !exponent.starts_with('-') => format!("{}e+{}", mantissa, exponent),
+        _ => formatted,
+    }
+}
+
+fn trim_decimals(val: f64) -> String {
+    let formatted = format!("{:.3}", val);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Puts a comma between every three digits of an integer string.
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, ch) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(ch);
+    }
+    grouped
+}
+
+impl fmt::Display for CookieAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Notation::Long))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseAmountError(String);
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "can't read \"{}\" as a cookie amount", self.0)
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl FromStr for CookieAmount {
+    type Err = ParseAmountError;
+
+    /// Accepts every notation the game displays: "1,234,567", "12.345 million",
+    /// "12.345M", "1.2e+45" and "Infinity". Suffixes are matched case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseAmountError(s.to_string());
+        let text = s.trim().replace(',', "");
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, text.as_str()),
+        };
+        if text.eq_ignore_ascii_case("infinity") {
+            return Ok(CookieAmount(if negative { f64::NEG_INFINITY } else { f64::INFINITY }));
+        }
+
+        // The number is digits and a decimal point, maybe followed by an exponent.
+        let mut end = text.find(|ch: char| !(ch.is_ascii_digit() || ch == '.')).unwrap_or(text.len());
+        let rest = &text[end..];
+        if rest.starts_with(['e', 'E']) {
+            let exponent = rest[1..].strip_prefix(['+', '-']).unwrap_or(&rest[1..]);
+            let digits = exponent.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(exponent.len());
+            if digits > 0 {
+                end = text.len() - exponent.len() + digits;
+            }
+        }
+        let number: f64 = text[..end].parse().map_err(|_| error())?;
+
+        // The space before a short suffix is kept: "1 T" is a trigintillion, "1T" a trillion.
+        let spaced = &text[end..];
+        let suffix = spaced.trim_start();
+        let multiplier = if suffix.is_empty() {
+            1.0
+        } else if suffix.eq_ignore_ascii_case("thousand") || suffix.eq_ignore_ascii_case("k") {
+            1e3
+        } else {
+            let short_names = short_names();
+            let power = long_names().iter()
+                .position(|name| name.eq_ignore_ascii_case(suffix))
+                .or_else(|| short_names.iter().position(|name| name.eq_ignore_ascii_case(spaced)))
+                .or_else(|| short_names.iter().position(|name| name.trim_start().eq_ignore_ascii_case(suffix)))
+                .ok_or_else(error)?;
+            10f64.powi(3 * (power as i32 + 2))
+        };
+
+        let value = number * multiplier;
+        Ok(CookieAmount(if negative { -value } else { value }))
+    }
+}
+
+impl From<f64> for CookieAmount {
+    fn from(value: f64) -> Self {
+        CookieAmount(value)
+    }
+}
+
+impl Add for CookieAmount {
+    type Output = CookieAmount;
+
+    fn add(self, rhs: CookieAmount) -> CookieAmount {
+        CookieAmount(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for CookieAmount {
+    fn add_assign(&mut self, rhs: CookieAmount) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for CookieAmount {
+    type Output = CookieAmount;
+
+    fn sub(self, rhs: CookieAmount) -> CookieAmount {
+        CookieAmount(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for CookieAmount {
+    fn sub_assign(&mut self, rhs: CookieAmount) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Mul<f64> for CookieAmount {
+    type Output = CookieAmount;
+
+    fn mul(self, rhs: f64) -> CookieAmount {
+        CookieAmount(self.0 * rhs)
+    }
+}
+
+impl Div<f64> for CookieAmount {
+    type Output = CookieAmount;
+
+    fn div(self, rhs: f64) -> CookieAmount {
+        CookieAmount(self.0 / rhs)
+    }
+}
+
+impl Sum for CookieAmount {
+    fn sum<I: Iterator<Item = CookieAmount>>(iter: I) -> CookieAmount {
+        iter.fold(CookieAmount::ZERO, |total, amount| total + amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> f64 {
+        text.parse::<CookieAmount>().unwrap().value()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= b.abs() * 1e-9
+    }
+
+    #[test]
+    fn formats_like_the_game() {
+        assert_eq!(CookieAmount(231_468.9).format(Notation::Long), "231,468");
+        assert_eq!(CookieAmount(12_345_678.0).format(Notation::Long), "12.346 million");
+        assert_eq!(CookieAmount(12_345_678.0).format(Notation::Short), "12.346M");
+        assert_eq!(CookieAmount(7e93).format(Notation::Short), "7 T");
+        assert_eq!(CookieAmount(1.5e45).format(Notation::Scientific), "1.50e+45");
+        assert_eq!(CookieAmount(-2e6).format(Notation::Long), "-2 million");
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        for value in [0.0, 15.0, 999_999.0, 1e6, 1.234e9, 5.5e33, 7e93, 1.5e123, 4.321e300] {
+            for notation in [Notation::Long, Notation::Short] {
+                let text = CookieAmount(value).format(notation);
+                assert!(close(parse(&text), value), "{} read back as {}", text, parse(&text));
+            }
+        }
+    }
+
+    #[test]
+    fn tells_spaced_short_suffixes_from_unspaced_ones() {
+        assert!(close(parse("7T"), 7e12));
+        assert!(close(parse("7 T"), 7e93));
+        assert!(close(parse("1.5Qa"), 1.5e15));
+        assert!(close(parse("1.5 Qa"), 1.5e123));
+        assert!(close(parse("2Sx"), 2e21));
+        assert!(close(parse("2 Sx"), 2e183));
+        assert!(close(parse("3UnD"), 3e36));
+    }
+
+    #[test]
+    fn reads_the_other_notations() {
+        assert_eq!(parse("231,468,481,229"), 231_468_481_229.0);
+        assert!(close(parse("12.345 Million"), 12_345_000.0));
+        assert!(close(parse("-1.2e+45"), -1.2e45));
+        assert_eq!(parse("Infinity"), f64::INFINITY);
+        assert!("12 zillion".parse::<CookieAmount>().is_err());
+        assert!("".parse::<CookieAmount>().is_err());
+    }
+}
@@ -0,0 +1,488 @@
+//! Reads numbers off the screen by matching digit templates.
+//!
+//! A template is a PNG of one digit with the ink drawn in opaque black, like
+//! the ones in `cookie_numbers/`. The game draws its counters in white, so a
+//! template matches wherever every one of its black pixels lands on white.
+//! Other text, like the green and red store prices, is read by changing the
+//! reader's `colour`.
+//! All digits sit on a common baseline; the bottom row of each template is
+//! lined up with it.
+//!
+//! Thousands separators and decimal points aren't templates. They're the small
+//! marks left over in the gaps between digits: a mark that hangs below the
+//! baseline is a comma, one that doesn't is a decimal point.
+//!
+//! Any other PNG in the template directory is a word the game puts after big
+//! numbers, named after the word itself (`million.png`). Words are looked for
+//! right after the last digit and may hang below the baseline.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use crate::my_screenshot::{Region, Screenshot};
+use crate::notation::CookieAmount;
+
+/// Anything the reader can look at pixel by pixel, with (0, 0) at the top left.
+pub trait PixelSource {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn rgb(&self, x: usize, y: usize) -> (u8, u8, u8);
+}
+
+impl PixelSource for Screenshot {
+    fn width(&self) -> usize { Screenshot::width(self) }
+
+    fn height(&self) -> usize { Screenshot::height(self) }
+
+    /// Panics outside the screenshot, like `RgbaImage` does.
+    fn rgb(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        match self.pixel(x, y) {
+            Some(pixel) => pixel.rgb(),
+            None => panic!("({}, {}) is outside a {}x{} screenshot", x, y, self.width(), self.height()),
+        }
+    }
+}
+
+impl PixelSource for RgbaImage {
+    fn width(&self) -> usize { RgbaImage::width(self) as usize }
+
+    fn height(&self) -> usize { RgbaImage::height(self) as usize }
+
+    fn rgb(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let [r, g, b, _] = self.get_pixel(x as u32, y as u32).0;
+        (r, g, b)
+    }
+}
+
+/// One recognised character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Glyph {
+    pub ch: char,
+    /// Left edge of the glyph.
+    pub x: usize,
+    /// The baseline the glyph sits on.
+    pub y: usize,
+    pub width: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+    /// What was read, as it appears on screen, e.g. `"231,468,481,229"` or `"12.345 million"`.
+    pub text: String,
+    /// Digits and separators; the word, if any, isn't in here.
+    pub glyphs: Vec<Glyph>,
+    /// The word after the number, e.g. `"million"`.
+    pub word: Option<String>,
+    /// `text` parsed as a number, word included. `None` if nothing was read.
+    pub value: Option<f64>,
+}
+
+impl Reading {
+    fn new(glyphs: Vec<Glyph>, word: Option<String>) -> Self {
+        let mut text: String = glyphs.iter().map(|glyph| glyph.ch).collect();
+        if let Some(word) = &word {
+            text.push(' ');
+            text.push_str(word);
+        }
+        let value = text.parse::<CookieAmount>().ok().map(CookieAmount::value);
+        Self { text, glyphs, word, value }
+    }
+
+    /// The reading as a cookie amount, if it's one the game could have written.
+    pub fn amount(&self) -> Option<CookieAmount> {
+        self.value.map(CookieAmount)
+    }
+
+    /// Right edge of the last digit or separator.
+    pub fn right(&self) -> Option<usize> {
+        self.glyphs.last().map(|glyph| glyph.x + glyph.width)
+    }
+
+    /// Left edge of the first digit or separator.
+    pub fn left(&self) -> Option<usize> {
+        self.glyphs.first().map(|glyph| glyph.x)
+    }
+
+    fn digits(&self) -> usize {
+        self.glyphs.iter().filter(|glyph| glyph.ch.is_ascii_digit()).count()
+    }
+}
+
+#[derive(Clone)]
+struct Template {
+    label: String,
+    width: usize,
+    height: usize,
+    /// Ink pixels as (dx, dy) from the template's bottom left corner, dy counting upwards.
+    ink: Vec<(usize, usize)>,
+    /// First inked column of the bottom row.
+    foot: usize,
+}
+
+pub struct DigitReader {
+    templates: Vec<Template>,
+    words: Vec<Template>,
+    /// Colour of the text to read. White unless changed.
+    pub colour: (u8, u8, u8),
+    /// How far off `colour` each channel may be and still count as text.
+    pub tolerance: u8,
+    /// Baseline of the last read in each region that found digits, in rows from
+    /// the top of the region. One reader reads several regions, like the rows of
+    /// the store, and is shared between threads, hence the map and the lock.
+    last_baselines: Mutex<HashMap<Region, usize>>,
+}
+
+/// How many regions' baselines a reader remembers. Regions that follow a moving
+/// number, like the per second line, are new every time it changes width, so the
+/// map is emptied once it's this big.
+const MAX_BASELINES: usize = 64;
+
+/// How many rows either side of the last baseline `read` tries before scanning
+/// the whole region.
+const BASELINE_SLACK: usize = 2;
+
+impl Clone for DigitReader {
+    fn clone(&self) -> Self {
+        Self {
+            templates: self.templates.clone(),
+            words: self.words.clone(),
+            colour: self.colour,
+            tolerance: self.tolerance,
+            last_baselines: Mutex::new(self.last_baselines.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl DigitReader {
+    /// Loads `0.png` to `9.png` from `dir`, plus any word templates next to them.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut templates = Vec::with_capacity(10);
+        for digit in 0..10u32 {
+            let path = dir.join(format!("{}.png", digit));
+            templates.push(Template::load(&digit.to_string(), &path)?);
+        }
+
+        let mut words = vec![];
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("can't read template directory {}", dir.display()))?;
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let word = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) if stem.chars().all(|ch| ch.is_ascii_alphabetic()) => stem.to_string(),
+                _ => continue,
+            };
+            if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+                words.push(Template::load(&word, &path)?);
+            }
+        }
+
+        // Bigger templates first: a 3 fits inside an 8, so the 8 has to get the first try.
+        // Likewise "million" has to lose to "septendecillion" if both fit.
+        templates.sort_by_key(|template| Reverse(template.ink.len()));
+        words.sort_by_key(|word| Reverse(word.ink.len()));
+
+        Ok(Self { templates, words, colour: (255, 255, 255), tolerance: 40, last_baselines: Mutex::new(HashMap::new()) })
+    }
+
+    /// Reads the number in `region`, keeping the baseline that yields the most digits.
+    /// Counters stay put from one frame to the next, so the rows around the baseline
+    /// of the last read of the same region are tried first; every row is only tried if that finds
+    /// nothing, or finds its best at the edge of those rows.
+    pub fn read<S: PixelSource>(&self, source: &S, region: Region) -> Reading {
+        let region = clamp(source, region);
+        let last = self.last_baselines.lock().unwrap().get(&region).copied();
+        if let Some(last) = last.filter(|&last| last < region.height) {
+            let from = (region.y + last).saturating_sub(BASELINE_SLACK).max(region.y);
+            let to = (region.y + last + BASELINE_SLACK + 1).min(region.bottom());
+            let reading = self.best_line(source, region, from, to);
+            if let Some(baseline) = reading.glyphs.first().map(|glyph| glyph.y) {
+                let inside = (baseline > from || from == region.y) && (baseline + 1 < to || to == region.bottom());
+                if inside {
+                    return reading;
+                }
+            }
+        }
+
+        let reading = self.best_line(source, region, region.y, region.bottom());
+        if let Some(glyph) = reading.glyphs.first() {
+            let mut last_baselines = self.last_baselines.lock().unwrap();
+            if last_baselines.len() >= MAX_BASELINES {
+                last_baselines.clear();
+            }
+            last_baselines.insert(region, glyph.y - region.y);
+        }
+        reading
+    }
+
+    /// The reading with the most digits among baselines `from..to`.
+    fn best_line<S: PixelSource>(&self, source: &S, region: Region, from: usize, to: usize) -> Reading {
+        (from..to)
+            .map(|baseline| self.read_line(source, region, baseline))
+            .fold(Reading::new(vec![], None), |best, reading| {
+                if reading.digits() > best.digits() { reading } else { best }
+            })
+    }
+
+    /// Reads the number whose digits sit on `baseline`, between the left and right
+    /// edges of `region`. The region's top and bottom only bound the separator search.
+    pub fn read_line<S: PixelSource>(&self, source: &S, region: Region, baseline: usize) -> Reading {
+        let region = clamp(source, region);
+        let mut digits: Vec<Glyph> = vec![];
+
+        let mut x = region.x;
+        while x < region.right() {
+            match self.match_at(source, region, x, baseline) {
+                Some(template) => {
+                    let left = x - template.foot;
+                    let ch = template.label.chars().next().unwrap();
+                    digits.push(Glyph { ch, x: left, y: baseline, width: template.width });
+                    x = left + template.width;
+                }
+                None => x += 1,
+            }
+        }
+
+        let mut glyphs: Vec<Glyph> = vec![];
+        for (i, digit) in digits.iter().enumerate() {
+            if i > 0 {
+                let previous = &digits[i - 1];
+                let gap_start = previous.x + previous.width;
+                if let Some(separator) = self.separator(source, region, gap_start, digit.x, baseline, digit) {
+                    glyphs.push(separator);
+                }
+            }
+            glyphs.push(*digit);
+        }
+
+        let word = glyphs.last().and_then(|last| self.word_after(source, region, last, baseline));
+        Reading::new(glyphs, word)
+    }
+
+    /// The leftmost pixel of text on `glyph`'s baseline. Every digit has one, where
+    /// its template's foot matched.
+    pub fn ink<S: PixelSource>(&self, source: &S, glyph: &Glyph) -> Option<(usize, usize)> {
+        if glyph.y >= source.height() {
+            return None;
+        }
+        (glyph.x..(glyph.x + glyph.width).min(source.width()))
+            .find(|&x| self.is_text(source, x, glyph.y))
+            .map(|x| (x, glyph.y))
+    }
+
+    /// Looks for a word template starting within a few digit widths after `last`.
+    fn word_after<S: PixelSource>(&self, source: &S, region: Region, last: &Glyph,
+                                  baseline: usize) -> Option<String> {
+        let from = last.x + last.width;
+        let to = (from + 3 * last.width).min(region.right());
+        for x in from..to {
+            if let Some(word) = self.words.iter().find(|word| self.word_at(source, region, word, x, baseline)) {
+                return Some(word.label.clone());
+            }
+        }
+        None
+    }
+
+    /// Whether `word`'s left edge fits at `x`, sitting on the baseline or hanging up to a
+    /// third of its height below it (for descenders like the p in "septillion").
+    fn word_at<S: PixelSource>(&self, source: &S, region: Region, word: &Template,
+                               x: usize, baseline: usize) -> bool {
+        if x + word.width > region.right() {
+            return false;
+        }
+        (0..=word.height / 3).any(|drop| {
+            let bottom = baseline + drop;
+            bottom < region.bottom() && bottom + 1 >= word.height
+                && word.ink.iter().all(|&(dx, dy)| self.is_text(source, x + dx, bottom - dy))
+        })
+    }
+
+    /// The biggest template whose foot is at `x` on the baseline and whose ink all lands on text.
+    fn match_at<S: PixelSource>(&self, source: &S, region: Region, x: usize, baseline: usize) -> Option<&Template> {
+        if !self.is_text(source, x, baseline) {
+            return None;
+        }
+        self.templates.iter().find(|template| {
+            if x < region.x + template.foot
+                || x - template.foot + template.width > region.right()
+                || baseline + 1 < template.height {
+                return false;
+            }
+            let left = x - template.foot;
+            template.ink.iter().all(|&(dx, dy)| self.is_text(source, left + dx, baseline - dy))
+        })
+    }
+
+    /// Looks for a separator between two digits. `digit` gives the height of the glyphs
+    /// around it; a separator has no ink in the upper half of that, and hangs no more than
+    /// half of it below the baseline, where the next line down may start. Marks touching either
+    /// digit, or only a pixel wide, are the digits' own anti-aliased edges.
+    fn separator<S: PixelSource>(&self, source: &S, region: Region, from: usize, to: usize,
+                                 baseline: usize, digit: &Glyph) -> Option<Glyph> {
+        let glyph_height = self.templates.iter()
+            .find(|template| template.label.starts_with(digit.ch))
+            .map_or(0, |template| template.height);
+        let top = (baseline + 1).saturating_sub(glyph_height).max(region.y);
+        let shoulder = baseline.saturating_sub(glyph_height / 2);
+        let bottom = (baseline + glyph_height / 2 + 1).min(region.bottom());
+
+        // Runs of columns that only have ink below the shoulder.
+        let mut runs: Vec<(usize, usize, bool)> = vec![];
+        let mut run: Option<(usize, usize, bool)> = None;
+        for x in from..to {
+            let mut lower = false;
+            let mut upper = false;
+            let mut below_baseline = false;
+            for y in top..bottom {
+                if self.is_text(source, x, y) {
+                    if y < shoulder { upper = true; } else { lower = true; }
+                    below_baseline |= y > baseline;
+                }
+            }
+            if lower && !upper {
+                let (start, _, below) = run.unwrap_or((x, x, false));
+                run = Some((start, x, below || below_baseline));
+            } else if let Some(finished) = run.take() {
+                runs.push(finished);
+            }
+        }
+        runs.extend(run);
+
+        runs.into_iter()
+            .filter(|&(start, end, _)| start > from && end + 1 < to && end > start)
+            .max_by_key(|&(start, end, _)| end - start)
+            .map(|(start, end, below_baseline)| Glyph {
+                ch: if below_baseline { ',' } else { '.' },
+                x: start,
+                y: baseline,
+                width: end - start + 1,
+            })
+    }
+
+    #[inline]
+    fn is_text<S: PixelSource>(&self, source: &S, x: usize, y: usize) -> bool {
+        let (r, g, b) = source.rgb(x, y);
+        let (tr, tg, tb) = self.colour;
+        r.abs_diff(tr) <= self.tolerance && g.abs_diff(tg) <= self.tolerance && b.abs_diff(tb) <= self.tolerance
+    }
+}
+
+impl Template {
+    fn load(label: &str, path: &Path) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("can't load template {}", path.display()))?
+            .to_rgba8();
+        Self::from_image(label, &image).with_context(|| format!("bad template {}", path.display()))
+    }
+
+    fn from_image(label: &str, image: &RgbaImage) -> Result<Self> {
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        let mut ink = vec![];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel.0 == [0, 0, 0, 255] {
+                ink.push((x as usize, height - 1 - y as usize));
+            }
+        }
+        let foot = match ink.iter().filter(|&&(_, dy)| dy == 0).map(|&(dx, _)| dx).min() {
+            Some(foot) => foot,
+            None => bail!("bottom row has no black pixels to line up with the baseline"),
+        };
+
+        Ok(Self { label: label.to_string(), width, height, ink, foot })
+    }
+}
+
+/// The part of `region` that's on `source`.
+pub(crate) fn clamp<S: PixelSource>(source: &S, region: Region) -> Region {
+    let x = region.x.min(source.width());
+    let y = region.y.min(source.height());
+    Region::new(x, y, region.width.min(source.width() - x), region.height.min(source.height() - y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops;
+
+    fn holder() -> RgbaImage {
+        image::open("test_holder.png").unwrap().to_rgba8()
+    }
+
+    fn whole(image: &RgbaImage) -> Region {
+        Region::new(0, 0, image.width() as usize, image.height() as usize)
+    }
+
+    #[test]
+    fn reads_the_test_holder() {
+        let reader = DigitReader::load("cookie_numbers").unwrap();
+        let image = holder();
+        let reading = reader.read(&image, whole(&image));
+        assert_eq!(reading.text, "231,468,481,229");
+        assert_eq!(reading.value, Some(231_468_481_229.0));
+        assert_eq!(reading.glyphs.iter().filter(|glyph| glyph.ch == ',').count(), 3);
+    }
+
+    #[test]
+    fn follows_the_number_when_it_moves() {
+        let reader = DigitReader::load("cookie_numbers").unwrap();
+        let image = holder();
+        let first = reader.read(&image, whole(&image));
+        assert_eq!(reader.read(&image, whole(&image)), first);
+
+        // Far enough down that the rows around the old baseline see nothing.
+        let mut shifted = RgbaImage::new(image.width(), image.height() + 20);
+        imageops::replace(&mut shifted, &image, 0, 20);
+        let moved = reader.read(&shifted, whole(&shifted));
+        assert_eq!(moved.text, first.text);
+        assert_eq!(moved.glyphs[0].y, first.glyphs[0].y + 20);
+    }
+
+    #[test]
+    fn remembers_the_baseline_of_each_region_apart() {
+        let reader = DigitReader::load("cookie_numbers").unwrap();
+        let image = holder();
+        let first = reader.read(&image, whole(&image));
+        let (left, width) = (first.glyphs[0].x as u32, first.glyphs[0].width as u32);
+
+        // The top region has the number, the bottom one a lone digit on the same
+        // baseline and the whole number further down.
+        let height = image.height();
+        let mut frame = RgbaImage::new(image.width(), height * 3 + 20);
+        imageops::replace(&mut frame, &image, 0, 0);
+        let digit = imageops::crop_imm(&image, left, 0, width, height).to_image();
+        imageops::replace(&mut frame, &digit, left as i64, height as i64);
+        imageops::replace(&mut frame, &image, 0, (height * 2 + 20) as i64);
+        let top = Region::new(0, 0, image.width() as usize, height as usize);
+        let bottom = Region::new(0, height as usize, image.width() as usize, (height * 2 + 20) as usize);
+
+        assert_eq!(reader.read(&frame, top).text, first.text);
+        assert_eq!(reader.read(&frame, bottom).text, first.text);
+        assert_eq!(reader.read(&frame, top).text, first.text);
+    }
+
+    #[test]
+    fn finds_ink_under_each_digit() {
+        let reader = DigitReader::load("cookie_numbers").unwrap();
+        let image = holder();
+        let reading = reader.read(&image, whole(&image));
+        for glyph in reading.glyphs.iter().filter(|glyph| glyph.ch.is_ascii_digit()) {
+            let (x, y) = reader.ink(&image, glyph).unwrap();
+            assert!(x >= glyph.x && x < glyph.x + glyph.width);
+            assert_eq!(y, glyph.y);
+            let (r, g, b) = image.rgb(x, y);
+            assert!(r > 200 && g > 200 && b > 200, "{:?} at ({}, {}) isn't text", (r, g, b), x, y);
+        }
+    }
+
+    #[test]
+    fn reads_nothing_off_a_blank_frame() {
+        let reader = DigitReader::load("cookie_numbers").unwrap();
+        let blank = RgbaImage::new(270, 43);
+        let reading = reader.read(&blank, whole(&blank));
+        assert_eq!(reading.text, "");
+        assert_eq!(reading.value, None);
+    }
+}
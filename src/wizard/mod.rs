@@ -0,0 +1,223 @@
+//! The calibration wizard: the user hovers over each thing the bot looks at or
+//! clicks and presses a hotkey, and the positions end up in a named `Profile`.
+//!
+//! C records the target under the cursor, Escape gives up. Only the big cookie,
+//! the upgrade slots and the building rows are asked for; the rest of the
+//! layout is placed relative to them the same way `Layout::calibrate` does.
+
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+use anyhow::{bail, Result};
+use enigo::{Enigo, MouseControllable};
+use inputbot::{handle_input_events, KeybdKey::*};
+use crate::building::Building;
+use crate::layout::Layout;
+use crate::layout::profile::{Profile, Sample};
+use crate::my_screenshot::{CaptureSource, Screenshot};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    BigCookie,
+    /// Counted from 0, left to right.
+    UpgradeSlot(usize),
+    Building(Building),
+}
+
+impl Target {
+    /// What to hover over, so the position means the same thing as in `Layout`.
+    fn hint(&self) -> &'static str {
+        match self {
+            Target::BigCookie => "its middle",
+            Target::UpgradeSlot(_) => "the top left corner of its frame, with an affordable upgrade in it",
+            Target::Building(_) => "a white pixel of the name, with the row affordable",
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::BigCookie => write!(f, "the big cookie"),
+            Target::UpgradeSlot(slot) => write!(f, "upgrade slot {}", slot + 1),
+            Target::Building(building) => write!(f, "{}'s row", building),
+        }
+    }
+}
+
+enum Key {
+    Record,
+    Abort,
+}
+
+/// Walks the user through every target and returns the result as profile `name`.
+/// `origin` is where the captured monitor's corner is on the desktop.
+pub fn run(name: &str, capturer: &mut dyn CaptureSource, origin: (i32, i32)) -> Result<Profile> {
+    let default = Layout::default();
+    let targets: Vec<Target> = std::iter::once(Target::BigCookie)
+        .chain((0..default.upgrade_slots.len()).map(Target::UpgradeSlot))
+        .chain(default.building_probes.iter().map(|&(building, _, _)| Target::Building(building)))
+        .collect();
+
+    // A key held down repeats; anything beyond one waiting press is dropped.
+    let (sender, keys) = mpsc::sync_channel(1);
+    let record = sender.clone();
+    CKey.bind(move || { let _ = record.try_send(Key::Record); });
+    EscapeKey.bind(move || { let _ = sender.try_send(Key::Abort); });
+    thread::spawn(|| {
+        handle_input_events();
+    });
+
+    let result = record_targets(&targets, &keys, capturer, origin);
+    CKey.unbind();
+    EscapeKey.unbind();
+    let (samples, resolution) = result?;
+
+    let layout = layout_from(&targets, &samples, resolution);
+    Ok(Profile { name: name.to_string(), resolution, layout, samples })
+}
+
+fn record_targets(
+    targets: &[Target],
+    keys: &mpsc::Receiver<Key>,
+    capturer: &mut dyn CaptureSource,
+    origin: (i32, i32),
+) -> Result<(Vec<Sample>, (usize, usize))> {
+    let enigo = Enigo::new();
+    let mut samples = vec![];
+    let mut resolution = (0, 0);
+
+    while samples.len() < targets.len() {
+        let target = targets[samples.len()];
+        println!("[{}/{}] hover over {}, {}, and press C (Escape to give up)",
+                 samples.len() + 1, targets.len(), target, target.hint());
+        match keys.recv()? {
+            Key::Record => {}
+            Key::Abort => bail!("calibration given up at {}", target),
+        }
+
+        let (x, y) = enigo.mouse_location();
+        let position = (x - origin.0, y - origin.1);
+        let screenshot = capturer.get_screenshot()?;
+        let colour = match colour_at(&screenshot, position) {
+            Some(colour) => colour,
+            None => {
+                println!("{:?} isn't on the captured screen, try again", position);
+                continue;
+            }
+        };
+        println!("    {} at {:?} is {:?}", target, position, colour);
+
+        resolution = (screenshot.width(), screenshot.height());
+        samples.push(Sample { target: target.to_string(), position, colour });
+    }
+
+    Ok((samples, resolution))
+}
+
+fn colour_at(screenshot: &Screenshot, (x, y): (i32, i32)) -> Option<(u8, u8, u8)> {
+    let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+    screenshot.pixel(x, y).map(|pixel| pixel.rgb())
+}
+
+/// The layout the samples describe. Slots and rows are where they were pointed
+/// at; everything else is scaled from how far apart the first and last row are.
+fn layout_from(targets: &[Target], samples: &[Sample], screen: (usize, usize)) -> Layout {
+    let default = Layout::default();
+    let position = |wanted: Target| {
+        targets.iter().zip(samples).find(|(&target, _)| target == wanted).map(|(_, sample)| sample.position)
+    };
+
+    let cookie = position(Target::BigCookie).unwrap_or(default.big_cookie);
+    let slots: Vec<(i32, i32)> = (0..default.upgrade_slots.len())
+        .filter_map(|slot| position(Target::UpgradeSlot(slot)))
+        .collect();
+    let probes: Vec<(Building, i32, i32)> = default.building_probes.iter()
+        .filter_map(|&(building, _, _)| position(Target::Building(building)).map(|(x, y)| (building, x, y)))
+        .collect();
+
+    // Measured against the same two rows at 1080p, which are only the first and the
+    // tenth if every row was sampled.
+    let default_y = |building: Building| default.building_probes.iter().find(|probe| probe.0 == building).map_or(0, |probe| probe.2);
+    let measured = probes.last().map_or(0, |last| last.2 - probes[0].2);
+    let expected = probes.last().map_or(0, |last| default_y(last.0) - default_y(probes[0].0));
+    let scale = if measured > 0 && expected > 0 { measured as f64 / expected as f64 } else { 1.0 };
+
+    let first_slot = slots.first().copied().unwrap_or(default.upgrade_slots[0]);
+    let store_left = first_slot.0 as f64 - (default.upgrade_slots[0].0 - default.store.x as i32) as f64 * scale;
+
+    let mut layout = Layout::anchored(screen, cookie, (store_left, first_slot.1 as f64), scale);
+    layout.upgrade_slots = slots;
+    layout.visible_rows = probes.len();
+    layout.building_probes = probes;
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Vec<Target> {
+        let default = Layout::default();
+        std::iter::once(Target::BigCookie)
+            .chain((0..default.upgrade_slots.len()).map(Target::UpgradeSlot))
+            .chain(default.building_probes.iter().map(|&(building, _, _)| Target::Building(building)))
+            .collect()
+    }
+
+    /// Samples at the 1080p layout's positions, scaled by `scale`.
+    fn samples(targets: &[Target], scale: f64) -> Vec<Sample> {
+        let default = Layout::default();
+        let scaled = |(x, y): (i32, i32)| ((x as f64 * scale).round() as i32, (y as f64 * scale).round() as i32);
+        targets.iter()
+            .map(|&target| {
+                let position = match target {
+                    Target::BigCookie => default.big_cookie,
+                    Target::UpgradeSlot(slot) => default.upgrade_slots[slot],
+                    Target::Building(building) => {
+                        let &(_, x, y) = default.building_probes.iter().find(|probe| probe.0 == building).unwrap();
+                        (x, y)
+                    }
+                };
+                Sample { target: target.to_string(), position: scaled(position), colour: (255, 255, 255) }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_at_the_default_positions_give_the_default_layout() {
+        let targets = targets();
+        assert_eq!(layout_from(&targets, &samples(&targets, 1.0), (1920, 1080)), Layout::default());
+    }
+
+    #[test]
+    fn scales_the_layout_by_how_far_apart_the_rows_are() {
+        let targets = targets();
+        let samples = samples(&targets, 1.5);
+        let layout = layout_from(&targets, &samples, (2880, 1620));
+
+        assert_eq!(layout.big_cookie, samples[0].position);
+        assert_eq!(layout.upgrade_slots[0], samples[1].position);
+        assert_eq!(layout.row_height, 96);
+        assert_eq!(layout.building_probes.len(), 10);
+        assert_eq!(layout.building_probes[2], (Building::Farm, samples[8].position.0, samples[8].position.1));
+        assert!((layout.scale() - 1.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn leaves_out_what_wasnt_sampled() {
+        let targets = targets();
+        let samples = samples(&targets, 1.0);
+        // Only the big cookie and the first three rows.
+        let (targets, samples): (Vec<Target>, Vec<Sample>) = targets.into_iter().zip(samples)
+            .filter(|(target, _)| matches!(target, Target::BigCookie | Target::Building(Building::Cursor | Building::Grandma | Building::Farm)))
+            .unzip();
+        let layout = layout_from(&targets, &samples, (1920, 1080));
+
+        assert_eq!(layout.big_cookie, Layout::default().big_cookie);
+        assert!(layout.upgrade_slots.is_empty());
+        assert_eq!(layout.visible_rows, 3);
+        assert_eq!(layout.row_height, Layout::default().row_height);
+        assert_eq!(layout.building_probes, Layout::default().building_probes[..3].to_vec());
+    }
+}